@@ -0,0 +1,110 @@
+// Compares the tailer reading on a fixed 100 ms poll with reading on Wakeups,
+// the notify driven loop: how long an appended record waits to be read, and
+// what an idle wakeup costs. Both drive the real Tail.
+use criterion::{criterion_group, criterion_main, Criterion};
+use logga_helper::configuration::{Framing, StartPosition};
+use logga_helper::forwarder::events::Wakeups;
+use logga_helper::forwarder::tail::Tail;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// The tailer's safety poll
+const SAFETY_POLL: Duration = Duration::from_secs(5);
+// Reads appended records, sending when each was read
+type Reader = fn(&Path, Sender<Instant>);
+
+const RECORD: &[u8] = b"127.0.0.1 - - [18/Oct/2026:10:00:00 +0000] \"GET / HTTP/1.1\" 200 612\n";
+
+fn tail(path: &Path, start: StartPosition) -> Tail {
+    Tail::new(path.display().to_string(), &Framing::default(), None, start).unwrap()
+}
+
+// Reads until the file has nothing new, then waits.
+fn follow(path: &Path, read: Sender<Instant>, wait: &dyn Fn()) {
+    let mut tail = tail(path, StartPosition::Beginning);
+    loop {
+        let records = tail.next().unwrap();
+        if records.is_empty() {
+            wait();
+            continue;
+        }
+        let position = tail.position();
+        tail.commit(position);
+        for _ in records {
+            if read.send(Instant::now()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn poll_reader(path: &Path, read: Sender<Instant>) {
+    follow(path, read, &|| sleep(POLL_INTERVAL));
+}
+
+fn notify_reader(path: &Path, read: Sender<Instant>) {
+    let wakeups = Wakeups::new(&[path.display().to_string()]);
+    follow(path, read, &|| {
+        wakeups.wait(SAFETY_POLL);
+    });
+}
+
+// Time from appending a record until the reader got it. Records are appended
+// at varying points of the poll interval, like requests arriving.
+fn latency(iters: u64, path: &Path, read: &Receiver<Instant>) -> Duration {
+    let mut log = OpenOptions::new().append(true).open(path).unwrap();
+    let mut total = Duration::ZERO;
+    for i in 0..iters {
+        sleep(Duration::from_millis(i * 37 % 100));
+        let written = Instant::now();
+        log.write_all(RECORD).unwrap();
+        total += read.recv().unwrap() - written;
+    }
+    total
+}
+
+fn bench_latency(c: &mut Criterion) {
+    let mut group = c.benchmark_group("latency");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(20));
+
+    let readers: [(&str, Reader); 2] = [("poll", poll_reader), ("notify", notify_reader)];
+    for (name, reader) in readers {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        File::create(&path).unwrap();
+        let (tx, read) = mpsc::channel();
+        let reader_path = path.clone();
+        thread::spawn(move || reader(&reader_path, tx));
+        // Let the reader settle into its wait
+        sleep(POLL_INTERVAL);
+
+        group.bench_function(name, |b| {
+            b.iter_custom(|iters| latency(iters, &path, &read))
+        });
+    }
+    group.finish();
+}
+
+// What one wakeup costs when there's nothing to read. The polling loop pays
+// this ten times a second, the notify driven one once per safety poll.
+fn bench_idle_read(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("access.log");
+    std::fs::write(&path, RECORD.repeat(1000)).unwrap();
+    let mut tail = tail(&path, StartPosition::End);
+    let wakeups = Wakeups::new(&[path.display().to_string()]);
+
+    let mut group = c.benchmark_group("idle_read");
+    group.bench_function("tail_next", |b| b.iter(|| tail.next().unwrap()));
+    group.bench_function("wakeups_wait", |b| b.iter(|| wakeups.wait(Duration::ZERO)));
+    group.finish();
+}
+
+criterion_group!(benches, bench_latency, bench_idle_read);
+criterion_main!(benches);
@@ -0,0 +1,22 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// Replaces the file at path atomically: data goes to a temp file next to it,
+// which is synced and renamed into place. A crash leaves either the old or the
+// new content behind, never a torn write.
+pub fn write(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let mut temp = File::create(&temp_path)?;
+    temp.write_all(data)?;
+    temp.sync_all()?;
+    fs::rename(&temp_path, path)?;
+    // The rename is only durable once the directory is synced
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,623 @@
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::process;
+
+use crate::flags::Flags;
+
+#[cfg(all(target_os = "macos", feature = "configuration-profile"))]
+mod profile;
+
+#[cfg(target_os = "macos")]
+const DEFAULT_AUDIT_LOG_PATH: &str = "/Library/Application Support/Logga/deletion-audit.log";
+#[cfg(not(target_os = "macos"))]
+const DEFAULT_AUDIT_LOG_PATH: &str = "/var/lib/logga/deletion-audit.log";
+#[cfg(target_os = "macos")]
+const DEFAULT_REGISTRY_PATH: &str = "/Library/Application Support/Logga/forwarder-registry.json";
+#[cfg(not(target_os = "macos"))]
+const DEFAULT_REGISTRY_PATH: &str = "/var/lib/logga/forwarder-registry.json";
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Configuration {
+    pub s3: S3,
+    // Empty means the watch-dir flag, uploading to s3.bucket
+    #[serde(default)]
+    pub watches: Vec<Watch>,
+    #[serde(default)]
+    pub watcher: WatcherConfig,
+    #[serde(default)]
+    pub mirror_deletions: MirrorDeletions,
+    // Several endpoints receiving the archives instead of s3.endpoint alone
+    #[serde(default)]
+    pub destination_group: Option<DestinationGroup>,
+    #[serde(default)]
+    pub integrity: Integrity,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    #[serde(default)]
+    pub forwarder: Forwarder,
+}
+
+// A watched directory and where its archives go.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Watch {
+    pub path: String,
+    // Glob patterns matched against the archives' file names
+    #[serde(default = "default_include")]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub destination: Destination,
+    #[serde(default)]
+    pub after_upload: AfterUpload,
+    // Bundles the archives into fewer, larger uploads
+    #[serde(default)]
+    pub batch: Option<Batch>,
+}
+
+// Unset fields fall back to the s3 section.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Destination {
+    pub bucket: Option<String>,
+    // Prepended to the rendered key template
+    pub prefix: String,
+    pub key_template: Option<String>,
+    pub storage_class: Option<String>,
+    // First matching rule wins, falling back to storage_class
+    pub storage_class_rules: Vec<StorageClassRule>,
+}
+
+// Picks a storage class for old or large archives. A rule without conditions
+// matches every archive.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageClassRule {
+    pub storage_class: String,
+    // Days since the archive was rotated
+    #[serde(default)]
+    pub min_age_days: Option<u64>,
+    #[serde(default)]
+    pub min_size_bytes: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AfterUpload {
+    // Removes the local archive once it's backed up
+    pub delete: bool,
+    // Moves the backed up archive into this directory instead
+    pub move_to: Option<String>,
+}
+
+// A bundle is uploaded once its archives reach max_bytes or the oldest waited
+// max_age_seconds, whichever comes first.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Batch {
+    pub max_bytes: u64,
+    pub max_age_seconds: u64,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Batch {
+            max_bytes: 64 * 1024 * 1024,
+            max_age_seconds: 3600,
+        }
+    }
+}
+
+impl AfterUpload {
+    // Whether the archive leaves the watched directory after its upload.
+    pub fn removes_archive(&self) -> bool {
+        self.delete || self.move_to.is_some()
+    }
+}
+
+fn default_include() -> Vec<String> {
+    vec![String::from("*.zip")]
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WatcherConfig {
+    // Quiet period after the last filesystem event before an archive is uploaded
+    pub debounce_ms: u64,
+    pub backend: WatcherBackend,
+    // How often the poll backend compares the directory against its last listing
+    pub poll_interval_ms: u64,
+    // Full directory rescans catching events the watcher missed, 0 disables them
+    pub rescan_interval_seconds: u64,
+}
+
+impl Default for WatcherConfig {
+    fn default() -> Self {
+        WatcherConfig {
+            debounce_ms: 2000,
+            backend: WatcherBackend::Native,
+            poll_interval_ms: 5000,
+            rescan_interval_seconds: 0,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum WatcherBackend {
+    // The platform's notification API, kqueue on macOS
+    Native,
+    // Periodic directory listings, for network filesystems which don't emit events
+    Poll,
+}
+
+// Opt-in removal of the backed up copy when an uploaded archive is deleted locally.
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MirrorDeletions {
+    pub enabled: bool,
+    pub mode: DeletionMode,
+    // Grace period before the object is touched, the archive may reappear meanwhile
+    pub delay_seconds: u64,
+    pub audit_log_path: String,
+}
+
+impl Default for MirrorDeletions {
+    fn default() -> Self {
+        MirrorDeletions {
+            enabled: false,
+            mode: DeletionMode::Delete,
+            delay_seconds: 300,
+            audit_log_path: String::from(DEFAULT_AUDIT_LOG_PATH),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum DeletionMode {
+    Delete,
+    // Keeps the object, adding a logga-deleted tag for lifecycle rules to act on
+    Tag,
+}
+
+// Ships the tailed access log to an HTTP endpoint in batches. A batch is sent
+// once it holds max_batch_bytes or max_batch_records, or its oldest data waited
+// max_latency_ms.
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Forwarder {
+    // Batches are POSTed here. Without it, loki or open_search nothing is forwarded
+    pub url: Option<String>,
+    // Pushes the batches to Grafana Loki instead
+    pub loki: Option<Loki>,
+    // Indexes the batches into OpenSearch or Elasticsearch instead
+    pub open_search: Option<OpenSearch>,
+    // Globs of the files to tail, empty means the access-log-path flag
+    pub paths: Vec<String>,
+    // How often the globs are matched again for new files
+    pub discovery_interval_seconds: u64,
+    // Safety net reading the files even without change events
+    pub poll_interval_ms: u64,
+    // Checkpoints of every tailed file
+    pub registry_path: String,
+    pub max_batch_bytes: usize,
+    pub max_batch_records: usize,
+    pub max_latency_ms: u64,
+    pub timeout_seconds: u64,
+    pub framing: Framing,
+    // The first parser whose path glob matches a file turns its lines into JSON
+    // records, files without one are forwarded as they are
+    pub parsers: Vec<Parser>,
+    // Where tailing starts without a usable checkpoint
+    pub start_position: StartPosition,
+    // How often advanced checkpoints are saved, besides on SIGINT and SIGTERM
+    pub checkpoint_interval_seconds: u64,
+}
+
+impl Default for Forwarder {
+    fn default() -> Self {
+        Forwarder {
+            url: None,
+            loki: None,
+            open_search: None,
+            paths: vec![],
+            discovery_interval_seconds: 10,
+            poll_interval_ms: 5000,
+            registry_path: String::from(DEFAULT_REGISTRY_PATH),
+            max_batch_bytes: 1024 * 1024,
+            max_batch_records: 1000,
+            max_latency_ms: 1000,
+            timeout_seconds: 30,
+            framing: Framing::default(),
+            parsers: vec![],
+            start_position: StartPosition::Beginning,
+            checkpoint_interval_seconds: 5,
+        }
+    }
+}
+
+// Every file is a stream labeled with host and filename, the static labels
+// are added and take precedence.
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Loki {
+    // Base URL, e.g. http://loki:3100
+    pub url: String,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub encoding: LokiEncoding,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum LokiEncoding {
+    // Snappy compressed
+    #[default]
+    Protobuf,
+    Json,
+}
+
+// Every record is a document, in the index its timestamp renders the index
+// template to, e.g. logga-{yyyy.MM.dd}.
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenSearch {
+    // Base URL, e.g. https://search:9200
+    pub url: String,
+    #[serde(default = "default_index")]
+    pub index: String,
+    #[serde(default)]
+    pub auth: Option<OpenSearchAuth>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum OpenSearchAuth {
+    Basic { username: String, password: String },
+    // Sent as `Authorization: ApiKey <key>`
+    ApiKey(String),
+}
+
+fn default_index() -> String {
+    String::from("logga-{yyyy.MM.dd}")
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum StartPosition {
+    // Forwards the whole file
+    Beginning,
+    // Only forwards what's written from now on
+    End,
+}
+
+// How the access log is cut into records. Longer records keep their first
+// max_record_bytes followed by the truncation marker.
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Framing {
+    // A single byte
+    pub delimiter: String,
+    pub max_record_bytes: usize,
+    pub truncation_marker: String,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Framing {
+            delimiter: String::from("\n"),
+            max_record_bytes: 64 * 1024,
+            truncation_marker: String::from("[truncated]"),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Parser {
+    pub path: String,
+    pub format: LogFormat,
+    // Regex with named captures, required by the regex format
+    #[serde(default)]
+    pub pattern: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum LogFormat {
+    // Common Log Format
+    Common,
+    // Common Log Format followed by the referer and the user agent
+    Combined,
+    // One JSON object per line
+    Json,
+    // key=value pairs
+    Logfmt,
+    Regex,
+}
+
+// Zip archives are checked before their upload, corrupt ones are quarantined.
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Integrity {
+    pub verify_zips: bool,
+    // Uploads corrupt archives anyway, tagged corrupt=true, instead of quarantining them
+    pub upload_corrupt: bool,
+}
+
+impl Default for Integrity {
+    fn default() -> Self {
+        Integrity {
+            verify_zips: true,
+            upload_corrupt: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HistoryConfig {
+    // Finished uploads of archives gone this many days are forgotten, 0 keeps them
+    pub retention_days: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig { retention_days: 30 }
+    }
+}
+
+// S3 compatible endpoints sharing the s3 section's credentials and TLS settings.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DestinationGroup {
+    pub mode: GroupMode,
+    // Replicate mode: confirmations an upload needs to succeed, all endpoints by default
+    #[serde(default)]
+    pub required_confirmations: Option<usize>,
+    // How often uploads are retried on endpoints lagging behind, 0 disables it
+    #[serde(default = "default_catch_up_interval")]
+    pub catch_up_interval_seconds: u64,
+    pub endpoints: Vec<Endpoint>,
+}
+
+fn default_catch_up_interval() -> u64 {
+    300
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum GroupMode {
+    // The first endpoint accepting the archive wins, the others are fallbacks
+    Failover,
+    // Every endpoint gets a copy
+    Replicate,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Endpoint {
+    // Identifies the endpoint in the upload history, keep it stable
+    pub name: String,
+    pub endpoint: String,
+    // Defaults to the s3 section's
+    #[serde(default)]
+    pub region: Option<String>,
+    #[serde(default)]
+    pub force_path_style: Option<bool>,
+    // Replaces the destination's bucket on this endpoint
+    #[serde(default)]
+    pub bucket: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct S3 {
+    pub bucket: String,
+    pub endpoint: String,
+    pub region: String,
+    pub keychain_authentication: bool,
+    // Required by MinIO / Ceph deployments not resolving virtual-hosted buckets
+    #[serde(default)]
+    pub force_path_style: bool,
+    // PEM bundle appended to the system trust store, e.g. for private CAs
+    #[serde(default)]
+    pub ca_bundle_path: Option<String>,
+    #[serde(default)]
+    pub proxy_url: Option<String>,
+    // Disables TLS certificate verification, test labs only!
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+    // Object key of uploaded archives, see template::Template for the placeholders
+    #[serde(default = "default_key_template")]
+    pub key_template: String,
+    // Default storage class of the destinations, e.g. STANDARD_IA
+    #[serde(default)]
+    pub storage_class: Option<String>,
+}
+
+fn default_key_template() -> String {
+    String::from("{filename}")
+}
+
+impl Configuration {
+    pub fn build(flags: &Flags) -> Configuration {
+        #[cfg(all(target_os = "macos", feature = "configuration-profile"))]
+        {
+            let profile_config =
+                Configuration::parse_configuration_profile(&flags.profile_path, &flags.bundle_id);
+            if let Some(c) = profile_config {
+                return c;
+            }
+        }
+
+        let config = match Configuration::parse_config_yaml(&flags.config_path) {
+            Ok(config) => config,
+            Err(err_string) => {
+                error!("Problem parsing config yaml: {err_string}");
+                process::exit(1)
+            }
+        };
+
+        config
+    }
+
+    // The configured watches, or watch_dir uploading every zip to s3.bucket.
+    pub fn watches(&self, watch_dir: &str) -> Vec<Watch> {
+        if !self.watches.is_empty() {
+            return self.watches.clone();
+        }
+        vec![Watch {
+            path: watch_dir.to_string(),
+            include: default_include(),
+            exclude: vec![],
+            destination: Destination::default(),
+            after_upload: AfterUpload::default(),
+            batch: None,
+        }]
+    }
+
+    fn parse_config_yaml(path: &String) -> Result<Configuration, Box<dyn std::error::Error>> {
+        let cfg_handle = std::fs::File::open(path)?;
+        serde_yaml::from_reader(cfg_handle).map_err(|e| e.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AfterUpload, Batch, Configuration, Destination, DestinationGroup, Endpoint, GroupMode,
+        StorageClassRule, Watch,
+    };
+
+    #[test]
+    fn parse_watches() {
+        let config: Configuration = serde_yaml::from_str(
+            "
+s3:
+  bucket: logs
+  endpoint: https://s3.example.com
+  region: eu-west-1
+  keychainAuthentication: false
+watches:
+  - path: /var/log/app1
+    exclude: ['*-partial.zip']
+    destination:
+      bucket: app1-logs
+      prefix: app1/
+      storageClass: STANDARD_IA
+      storageClassRules:
+        - minAgeDays: 30
+          storageClass: GLACIER_IR
+    afterUpload:
+      delete: true
+  - path: /var/log/app2
+    batch:
+      maxAgeSeconds: 600
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.watches("/ignored"),
+            vec![
+                Watch {
+                    path: String::from("/var/log/app1"),
+                    include: vec![String::from("*.zip")],
+                    exclude: vec![String::from("*-partial.zip")],
+                    destination: Destination {
+                        bucket: Some(String::from("app1-logs")),
+                        prefix: String::from("app1/"),
+                        key_template: None,
+                        storage_class: Some(String::from("STANDARD_IA")),
+                        storage_class_rules: vec![StorageClassRule {
+                            storage_class: String::from("GLACIER_IR"),
+                            min_age_days: Some(30),
+                            min_size_bytes: None,
+                        }],
+                    },
+                    after_upload: AfterUpload {
+                        delete: true,
+                        move_to: None,
+                    },
+                    batch: None,
+                },
+                Watch {
+                    path: String::from("/var/log/app2"),
+                    include: vec![String::from("*.zip")],
+                    exclude: vec![],
+                    destination: Destination::default(),
+                    after_upload: AfterUpload::default(),
+                    batch: Some(Batch {
+                        max_bytes: 64 * 1024 * 1024,
+                        max_age_seconds: 600,
+                    }),
+                },
+            ]
+        )
+    }
+
+    #[test]
+    fn watch_dir_without_watches() {
+        let config = Configuration::default();
+        let watches = config.watches("/var/log/logga");
+
+        assert_eq!(watches.len(), 1);
+        assert_eq!(watches[0].path, "/var/log/logga");
+        assert_eq!(watches[0].include, vec![String::from("*.zip")]);
+    }
+
+    #[test]
+    fn parse_destination_group() {
+        let config: Configuration = serde_yaml::from_str(
+            "
+s3:
+  bucket: logs
+  endpoint: https://s3.example.com
+  region: eu-west-1
+  keychainAuthentication: false
+destinationGroup:
+  mode: replicate
+  requiredConfirmations: 1
+  endpoints:
+    - name: primary
+      endpoint: https://s3.example.com
+    - name: backup
+      endpoint: https://minio.example.com
+      forcePathStyle: true
+      bucket: logs-replica
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.destination_group,
+            Some(DestinationGroup {
+                mode: GroupMode::Replicate,
+                required_confirmations: Some(1),
+                catch_up_interval_seconds: 300,
+                endpoints: vec![
+                    Endpoint {
+                        name: String::from("primary"),
+                        endpoint: String::from("https://s3.example.com"),
+                        region: None,
+                        force_path_style: None,
+                        bucket: None,
+                    },
+                    Endpoint {
+                        name: String::from("backup"),
+                        endpoint: String::from("https://minio.example.com"),
+                        region: None,
+                        force_path_style: Some(true),
+                        bucket: Some(String::from("logs-replica")),
+                    },
+                ],
+            })
+        )
+    }
+}
@@ -1,3 +1,5 @@
+// MDM configuration profiles, read from the managed preferences through CoreFoundation.
+// Only built on macOS with the configuration-profile feature.
 use core::ptr;
 use core_foundation_sys::base::kCFAllocatorNull;
 use core_foundation_sys::base::CFRelease;
@@ -10,15 +12,12 @@ use core_foundation_sys::string::kCFStringEncodingUTF8;
 use core_foundation_sys::string::CFStringCreateWithBytesNoCopy;
 use core_foundation_sys::string::CFStringGetCStringPtr;
 use core_foundation_sys::string::CFStringRef;
-use log::{error, warn};
-use serde::Deserialize;
-use serde_yaml;
+use log::warn;
 use std::collections::HashMap;
 use std::fmt;
 use std::path::Path;
-use std::process;
 
-use crate::flags::Flags;
+use super::{default_key_template, Configuration, S3};
 
 #[derive(Debug, Eq, Hash, PartialEq)]
 enum LabelKey {
@@ -26,6 +25,12 @@ enum LabelKey {
     S3Endpoint,
     S3Region,
     S3KeychainAuthentication,
+    S3ForcePathStyle,
+    S3CABundlePath,
+    S3ProxyURL,
+    S3InsecureSkipVerify,
+    S3KeyTemplate,
+    S3StorageClass,
 }
 
 impl From<&LabelKey> for &str {
@@ -35,6 +40,12 @@ impl From<&LabelKey> for &str {
             LabelKey::S3Endpoint => "S3Endpoint",
             LabelKey::S3Region => "S3Region",
             LabelKey::S3KeychainAuthentication => "S3KeychainAuthentication",
+            LabelKey::S3ForcePathStyle => "S3ForcePathStyle",
+            LabelKey::S3CABundlePath => "S3CABundlePath",
+            LabelKey::S3ProxyURL => "S3ProxyURL",
+            LabelKey::S3InsecureSkipVerify => "S3InsecureSkipVerify",
+            LabelKey::S3KeyTemplate => "S3KeyTemplate",
+            LabelKey::S3StorageClass => "S3StorageClass",
         }
     }
 }
@@ -49,7 +60,7 @@ trait PreferenceTrait<T> {
     fn get_preference_val(
         &self,
         bundle_id_key: *const __CFString,
-    ) -> Result<Option<T>, ProfileError>;
+    ) -> Result<Option<T>, ProfileError<'_>>;
 }
 
 pub enum ProfileError<'a> {
@@ -70,23 +81,8 @@ impl fmt::Display for ProfileError<'_> {
     }
 }
 
-#[derive(Deserialize, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct Configuration {
-    pub s3: S3,
-}
-
-#[derive(Deserialize, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct S3 {
-    pub bucket: String,
-    pub endpoint: String,
-    pub region: String,
-    pub keychain_authentication: bool,
-}
-
 impl S3 {
-    fn validate(&self) -> Result<(), ProfileError> {
+    fn validate(&self) -> Result<(), ProfileError<'_>> {
         if self.bucket.is_empty() {
             return Err(ProfileError::ValidateEmpty("bucket"));
         }
@@ -101,30 +97,7 @@ impl S3 {
 }
 
 impl Configuration {
-    pub fn build(flags: &Flags) -> Configuration {
-        let profile_config =
-            Configuration::parse_configuration_profile(&flags.profile_path, &flags.bundle_id);
-        if let Some(c) = profile_config {
-            return c;
-        }
-
-        let config = match Configuration::parse_config_yaml(&flags.config_path) {
-            Ok(config) => config,
-            Err(err_string) => {
-                error!("Problem parsing config yaml: {err_string}");
-                process::exit(1)
-            }
-        };
-
-        config
-    }
-
-    fn parse_config_yaml(path: &String) -> Result<Configuration, Box<dyn std::error::Error>> {
-        let cfg_handle = std::fs::File::open(path)?;
-        serde_yaml::from_reader(cfg_handle).map_err(|e| e.into())
-    }
-
-    fn parse_configuration_profile(
+    pub fn parse_configuration_profile(
         profile_path: &String,
         bundle_id: &String,
     ) -> Option<Configuration> {
@@ -135,14 +108,22 @@ impl Configuration {
         let mut preferences: HashMap<LabelKey, Option<String>> = HashMap::new();
 
         unsafe {
-            let bundle_id_key = static_cf_string(&bundle_id);
+            let bundle_id_key = static_cf_string(bundle_id);
 
             if bundle_id_key.is_null() {
                 warn!("Problem creating bundle_id_key");
                 return None;
             }
 
-            for label in vec![LabelKey::S3Region, LabelKey::S3Bucket, LabelKey::S3Endpoint] {
+            for label in [
+                LabelKey::S3Region,
+                LabelKey::S3Bucket,
+                LabelKey::S3Endpoint,
+                LabelKey::S3CABundlePath,
+                LabelKey::S3ProxyURL,
+                LabelKey::S3KeyTemplate,
+                LabelKey::S3StorageClass,
+            ] {
                 let preference_str = match label.get_preference_val(bundle_id_key) {
                     Ok(value) => value,
                     Err(err) => {
@@ -153,14 +134,21 @@ impl Configuration {
                 preferences.insert(label, preference_str);
             }
 
-            let keychain_auth_bool: Option<bool> =
-                match LabelKey::S3KeychainAuthentication.get_preference_val(bundle_id_key) {
+            let mut flags: HashMap<LabelKey, Option<bool>> = HashMap::new();
+            for label in [
+                LabelKey::S3KeychainAuthentication,
+                LabelKey::S3ForcePathStyle,
+                LabelKey::S3InsecureSkipVerify,
+            ] {
+                let preference_bool = match label.get_preference_val(bundle_id_key) {
                     Ok(value) => value,
                     Err(err) => {
                         warn!("{}", err);
                         return None;
                     }
                 };
+                flags.insert(label, preference_bool);
+            }
             CFRelease(bundle_id_key.cast());
 
             let s3 = S3 {
@@ -173,7 +161,16 @@ impl Configuration {
                 region: preferences[&LabelKey::S3Region]
                     .to_owned()
                     .unwrap_or_else(|| String::from("us-east-1")),
-                keychain_authentication: keychain_auth_bool.to_owned().unwrap_or_default(),
+                keychain_authentication: flags[&LabelKey::S3KeychainAuthentication]
+                    .unwrap_or_default(),
+                force_path_style: flags[&LabelKey::S3ForcePathStyle].unwrap_or_default(),
+                ca_bundle_path: preferences[&LabelKey::S3CABundlePath].to_owned(),
+                proxy_url: preferences[&LabelKey::S3ProxyURL].to_owned(),
+                insecure_skip_verify: flags[&LabelKey::S3InsecureSkipVerify].unwrap_or_default(),
+                key_template: preferences[&LabelKey::S3KeyTemplate]
+                    .to_owned()
+                    .unwrap_or_else(default_key_template),
+                storage_class: preferences[&LabelKey::S3StorageClass].to_owned(),
             };
 
             match s3.validate() {
@@ -184,7 +181,10 @@ impl Configuration {
                 }
             }
 
-            Some(Configuration { s3 })
+            Some(Configuration {
+                s3,
+                ..Default::default()
+            })
         }
     }
 }
@@ -193,7 +193,7 @@ impl LabelKey {
     fn read_preference(
         &self,
         bundle_id_key: *const __CFString,
-    ) -> Result<CFPropertyListRef, ProfileError> {
+    ) -> Result<CFPropertyListRef, ProfileError<'_>> {
         let key = static_cf_string(self.into());
         if key.is_null() {
             return Err(ProfileError::CreateKey(self.into()));
@@ -210,7 +210,7 @@ impl PreferenceTrait<String> for LabelKey {
     fn get_preference_val(
         &self,
         bundle_id_key: *const __CFString,
-    ) -> Result<Option<String>, ProfileError> {
+    ) -> Result<Option<String>, ProfileError<'_>> {
         Ok(cf_string_to_string(self.read_preference(bundle_id_key)?))
     }
 }
@@ -219,7 +219,7 @@ impl PreferenceTrait<bool> for LabelKey {
     fn get_preference_val(
         &self,
         bundle_id_key: *const __CFString,
-    ) -> Result<Option<bool>, ProfileError> {
+    ) -> Result<Option<bool>, ProfileError<'_>> {
         Ok(cf_bool_to_bool(self.read_preference(bundle_id_key)?))
     }
 }
@@ -0,0 +1,96 @@
+use crate::configuration::DeletionMode;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+// Actions the helper would have taken, printed as JSON lines on stdout in dry-run mode.
+// Logs go to stderr, so the output can be consumed as-is by integration tests.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum DryRunEvent<'a> {
+    #[serde(rename_all = "camelCase")]
+    Upload {
+        path: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        endpoint: Option<&'a str>,
+        bucket: &'a str,
+        key: &'a str,
+        size: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        storage_class: Option<&'a str>,
+        metadata: &'a HashMap<String, String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Bundle {
+        members: Vec<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        endpoint: Option<&'a str>,
+        bucket: &'a str,
+        key: &'a str,
+        size: u64,
+    },
+    #[serde(rename_all = "camelCase")]
+    Quarantine { path: &'a str, reason: &'a str },
+    #[serde(rename_all = "camelCase")]
+    MirrorDeletion {
+        path: &'a str,
+        bucket: &'a str,
+        key: &'a str,
+        mode: DeletionMode,
+    },
+    #[serde(rename_all = "camelCase")]
+    Forward {
+        url: &'a str,
+        bytes: usize,
+        payload: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Checkpoint { path: &'a str, offset: u64 },
+}
+
+pub fn report(event: &DryRunEvent) {
+    if let Ok(line) = serde_json::to_string(event) {
+        let mut stdout = io::stdout().lock();
+        let _ = writeln!(stdout, "{}", line);
+        let _ = stdout.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DryRunEvent;
+    use std::collections::HashMap;
+
+    #[test]
+    fn upload_event_json() {
+        let metadata = HashMap::from([(String::from("host"), String::from("mac-01"))]);
+        let event = DryRunEvent::Upload {
+            path: "/tmp/a.zip",
+            endpoint: None,
+            bucket: "logs",
+            key: "a.zip",
+            size: 42,
+            storage_class: None,
+            metadata: &metadata,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"action":"upload","path":"/tmp/a.zip","bucket":"logs","key":"a.zip","size":42,"metadata":{"host":"mac-01"}}"#
+        )
+    }
+
+    #[test]
+    fn forward_event_json() {
+        let event = DryRunEvent::Forward {
+            url: "http://collector",
+            bytes: 3,
+            payload: String::from("GET"),
+        };
+
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"action":"forward","url":"http://collector","bytes":3,"payload":"GET"}"#
+        )
+    }
+}
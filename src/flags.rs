@@ -1,13 +1,34 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+use time::macros::format_description;
+use time::Date;
 
-const DEFAULT_CONFIG_PATH: &str = "/Library/Application Support/Logga/config.yaml";
-const DEFAULT_ACCESS_LOG_PATH: &str = "/Library/Application Support/Logga/access.log";
 const DEFAULT_PROFILE_PATH: &str = "/Library/Managed Preferences/com.logga.client.plist";
 const DEFAULT_BUNDLE_ID: &str = "com.logga.client";
+
+#[cfg(target_os = "macos")]
+const DEFAULT_CONFIG_PATH: &str = "/Library/Application Support/Logga/config.yaml";
+#[cfg(target_os = "macos")]
+const DEFAULT_ACCESS_LOG_PATH: &str = "/Library/Application Support/Logga/access.log";
+#[cfg(target_os = "macos")]
 const DEFAULT_WATCH_DIR: &str = "/Library/Application Support/Logga";
+#[cfg(target_os = "macos")]
+const DEFAULT_HISTORY_PATH: &str = "/Library/Application Support/Logga/upload-history.json";
+
+// Configuration under /etc, logs and archives under /var/log, state under /var/lib
+#[cfg(not(target_os = "macos"))]
+const DEFAULT_CONFIG_PATH: &str = "/etc/logga/config.yaml";
+#[cfg(not(target_os = "macos"))]
+const DEFAULT_ACCESS_LOG_PATH: &str = "/var/log/logga/access.log";
+#[cfg(not(target_os = "macos"))]
+const DEFAULT_WATCH_DIR: &str = "/var/log/logga";
+#[cfg(not(target_os = "macos"))]
+const DEFAULT_HISTORY_PATH: &str = "/var/lib/logga/upload-history.json";
 
 #[derive(Parser)]
 pub struct Flags {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[arg(short, long, value_name = "config-path", default_value_t = DEFAULT_CONFIG_PATH.to_string())]
     pub config_path: String,
 
@@ -22,6 +43,73 @@ pub struct Flags {
 
     #[arg(short, long, value_name = "access-log-path", default_value_t = DEFAULT_ACCESS_LOG_PATH.to_string())]
     pub access_log_path: String,
+
+    #[arg(long, value_name = "history-path", default_value_t = DEFAULT_HISTORY_PATH.to_string())]
+    pub history_path: String,
+
+    /// Log the uploads and forwarded payloads as JSON lines instead of sending them
+    #[arg(short, long)]
+    pub dry_run: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Print pending, failed and completed archive uploads
+    Status {
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print a service definition running the helper, the launchd plist by default
+    Install {
+        /// Print a systemd unit with readiness notification and a watchdog instead
+        #[arg(long)]
+        systemd: bool,
+    },
+    /// List archives backed up to the bucket
+    List {
+        #[command(flatten)]
+        filter: ArchiveFilter,
+
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Download archives from the bucket
+    Restore {
+        #[command(flatten)]
+        filter: ArchiveFilter,
+
+        #[arg(short, long, value_name = "output-dir", default_value_t = String::from("."))]
+        output_dir: String,
+
+        /// Number of archives downloaded in parallel
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+    },
+}
+
+#[derive(Args, Clone, Default)]
+pub struct ArchiveFilter {
+    /// Only archives uploaded by this host
+    #[arg(long)]
+    pub host: Option<String>,
+
+    /// Only archives rotated on or after this date (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    pub from: Option<Date>,
+
+    /// Only archives rotated on or before this date (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    pub to: Option<Date>,
+
+    /// Only keys starting with this prefix
+    #[arg(long)]
+    pub prefix: Option<String>,
+}
+
+fn parse_date(value: &str) -> Result<Date, String> {
+    Date::parse(value, format_description!("[year]-[month]-[day]")).map_err(|err| err.to_string())
 }
 
 impl Flags {
@@ -29,11 +117,14 @@ impl Flags {
         let cli = Flags::parse();
 
         Flags {
+            command: cli.command,
             config_path: cli.config_path,
             profile_path: cli.profile_path,
             bundle_id: cli.bundle_id,
             watch_dir: cli.watch_dir,
             access_log_path: cli.access_log_path,
+            history_path: cli.history_path,
+            dry_run: cli.dry_run,
         }
     }
 }
@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+// Start of the file hashed into its fingerprint, telling a recycled inode apart
+const FINGERPRINT_BYTES: u64 = 1024;
+
+// The offset and the identity of the file it belongs to.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Checkpoint {
+    pub offset: u64,
+    pub device: u64,
+    pub inode: u64,
+    // Hex SHA-256 of the file's first fingerprint_bytes, fewer while it's smaller
+    pub fingerprint: String,
+    pub fingerprint_bytes: u64,
+}
+
+// What to read after a restart.
+pub enum Resume {
+    At(u64),
+    // The tailed file was replaced by a new one
+    Restart,
+    // The checkpoint's file was rotated away with data left, read it to the end
+    // before starting the new file
+    Drain {
+        file: File,
+        path: PathBuf,
+        offset: u64,
+    },
+}
+
+impl Checkpoint {
+    pub fn new(file: &File, offset: u64) -> io::Result<Checkpoint> {
+        let md = file.metadata()?;
+        let fingerprint_bytes = md.len().min(FINGERPRINT_BYTES);
+        Ok(Checkpoint {
+            offset,
+            device: md.dev(),
+            inode: md.ino(),
+            fingerprint: fingerprint(file, fingerprint_bytes)?.unwrap_or_default(),
+            fingerprint_bytes,
+        })
+    }
+
+    // Same device and inode, still starting with the fingerprinted bytes.
+    pub fn matches(&self, file: &File) -> io::Result<bool> {
+        let md = file.metadata()?;
+        if md.dev() != self.device || md.ino() != self.inode {
+            return Ok(false);
+        }
+        Ok(fingerprint(file, self.fingerprint_bytes)?.as_ref() == Some(&self.fingerprint))
+    }
+}
+
+// Resumes when the file at path is still the checkpoint's file. Otherwise the
+// old file is looked for next to it, e.g. renamed to access.log.1.
+pub fn resume(checkpoint: &Checkpoint, path: &Path, file: &File) -> io::Result<Resume> {
+    if checkpoint.matches(file)? {
+        return Ok(Resume::At(checkpoint.offset));
+    }
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let md = match entry.metadata() {
+            Ok(md) => md,
+            Err(_) => continue,
+        };
+        if !md.is_file() || md.dev() != checkpoint.device || md.ino() != checkpoint.inode {
+            continue;
+        }
+
+        let rotated = File::open(entry.path())?;
+        if !checkpoint.matches(&rotated)? || md.len() <= checkpoint.offset {
+            break;
+        }
+        return Ok(Resume::Drain {
+            file: rotated,
+            path: entry.path(),
+            offset: checkpoint.offset,
+        });
+    }
+
+    Ok(Resume::Restart)
+}
+
+// None when the file is shorter than bytes.
+fn fingerprint(file: &File, bytes: u64) -> io::Result<Option<String>> {
+    if file.metadata()?.len() < bytes {
+        return Ok(None);
+    }
+    let mut data = vec![0; bytes as usize];
+    file.read_exact_at(&mut data, 0)?;
+    Ok(Some(hex::encode(Sha256::digest(&data))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resume, Checkpoint, Resume};
+    use std::fs::{self, File};
+
+    #[test]
+    fn resumes_restarts_or_drains() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("access.log");
+        fs::write(&log, "GET / 200\nGET /a 404\n").unwrap();
+        let checkpoint = Checkpoint::new(&File::open(&log).unwrap(), 10).unwrap();
+
+        let current = File::open(&log).unwrap();
+        assert!(matches!(
+            resume(&checkpoint, &log, &current).unwrap(),
+            Resume::At(10)
+        ));
+
+        // Rotated away with a record left, a new file took its name
+        let rotated = dir.path().join("access.log.1");
+        fs::rename(&log, &rotated).unwrap();
+        fs::write(&log, "GET /b 200\n").unwrap();
+        let current = File::open(&log).unwrap();
+        match resume(&checkpoint, &log, &current).unwrap() {
+            Resume::Drain { path, offset, .. } => {
+                assert_eq!(path, rotated);
+                assert_eq!(offset, 10);
+            }
+            _ => panic!("expected the rotated file to be drained"),
+        }
+
+        // Nothing left in the rotated file
+        fs::remove_file(&rotated).unwrap();
+        assert!(matches!(
+            resume(&checkpoint, &log, &current).unwrap(),
+            Resume::Restart
+        ));
+    }
+}
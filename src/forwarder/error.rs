@@ -1,14 +1,16 @@
-use std::num::ParseIntError;
+use crate::template::TemplateError;
 use std::{fmt, io};
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum TailError {
     FileOpenError(String),
     ReadMetadataError(io::Error),
-    StartSeekError(io::Error),
     BufferReadError(io::Error),
     FileOperationError(io::Error),
-    CastError(ParseIntError),
+    CheckpointError(io::Error),
+    InvalidDelimiter(String),
+    PatternError(String, glob::PatternError),
 }
 
 impl fmt::Display for TailError {
@@ -16,10 +18,15 @@ impl fmt::Display for TailError {
         match self {
             TailError::FileOpenError(path) => write!(f, "failed to open {}", path),
             TailError::ReadMetadataError(err) => write!(f, "read metadata: {:?}", err),
-            TailError::StartSeekError(err) => write!(f, "start seeking: {:?}", err),
             TailError::BufferReadError(err) => write!(f, "buffer reader: {:?}", err),
             TailError::FileOperationError(err) => write!(f, "detecting file operation: {:?}", err),
-            TailError::CastError(err) => write!(f, "cast string to int: {:?}", err),
+            TailError::CheckpointError(err) => write!(f, "checkpoint: {:?}", err),
+            TailError::InvalidDelimiter(delimiter) => {
+                write!(f, "delimiter must be a single byte, got {:?}", delimiter)
+            }
+            TailError::PatternError(pattern, err) => {
+                write!(f, "invalid pattern {}: {}", pattern, err)
+            }
         }
     }
 }
@@ -29,3 +36,70 @@ impl From<std::io::Error> for TailError {
         TailError::ReadMetadataError(err)
     }
 }
+
+#[derive(Debug)]
+pub enum TransmitError {
+    Request(reqwest::Error),
+    // The server answered, but didn't acknowledge the batch
+    Status(u16),
+    // The server won't ever accept the batch, with its reason
+    Rejected(u16, String),
+    Encode(String),
+    // Some of the batch's documents failed, the rest was accepted
+    Documents {
+        failed: usize,
+        of: usize,
+        reason: String,
+    },
+    UnexpectedResponse(String),
+    Index(TemplateError),
+    // More than one of url, loki and openSearch is configured
+    SeveralSinks,
+}
+
+impl TransmitError {
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, TransmitError::Rejected(..))
+    }
+}
+
+impl fmt::Display for TransmitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransmitError::Request(err) => write!(f, "sending batch: {}", err),
+            TransmitError::Status(status) => write!(f, "server answered {}", status),
+            TransmitError::Rejected(status, reason) => {
+                write!(f, "server rejected the batch with {}: {}", status, reason)
+            }
+            TransmitError::Encode(err) => write!(f, "encoding batch: {}", err),
+            TransmitError::Documents { failed, of, reason } => {
+                write!(f, "{} of {} documents failed: {}", failed, of, reason)
+            }
+            TransmitError::UnexpectedResponse(err) => write!(f, "unexpected response: {}", err),
+            TransmitError::Index(err) => write!(f, "invalid index: {}", err),
+            TransmitError::SeveralSinks => {
+                write!(f, "configure only one of url, loki and openSearch")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParserError {
+    Pattern(String, glob::PatternError),
+    Regex(String, regex::Error),
+    // The regex format without a pattern
+    MissingRegex(String),
+}
+
+impl fmt::Display for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParserError::Pattern(path, err) => write!(f, "invalid parser path {}: {}", path, err),
+            ParserError::Regex(path, err) => write!(f, "invalid regex for {}: {}", path, err),
+            ParserError::MissingRegex(path) => {
+                write!(f, "regex parser for {} needs a pattern", path)
+            }
+        }
+    }
+}
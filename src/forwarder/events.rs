@@ -0,0 +1,144 @@
+use log::{debug, warn};
+use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread::sleep;
+use std::time::Duration;
+
+// Polling interval when the platform's notifications aren't available
+const FALLBACK_INTERVAL: Duration = Duration::from_millis(100);
+
+pub enum Wakeup {
+    // Files were written to
+    Modified,
+    // Files were created, renamed or removed, the globs may match others now
+    Changed,
+    Timeout,
+}
+
+// Wakes the tailer when the directories of its globs change, instead of
+// polling them. Without notifications it falls back to polling.
+pub struct Wakeups {
+    // Kept alive for as long as events are wanted
+    _watcher: Option<RecommendedWatcher>,
+    events: Receiver<notify::Result<Event>>,
+}
+
+impl Wakeups {
+    pub fn new(patterns: &[String]) -> Wakeups {
+        let (tx, events) = mpsc::channel();
+        let watcher = match watch(patterns, tx) {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                warn!(
+                    "watching the tailed files failed, polling every {:?}: {}",
+                    FALLBACK_INTERVAL, err
+                );
+                None
+            }
+        };
+
+        Wakeups {
+            _watcher: watcher,
+            events,
+        }
+    }
+
+    // Waits for the next event or timeout, whichever comes first. Events that
+    // queued up meanwhile are folded into one wakeup.
+    pub fn wait(&self, timeout: Duration) -> Wakeup {
+        let event = match self.events.recv_timeout(timeout) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => return Wakeup::Timeout,
+            Err(RecvTimeoutError::Disconnected) => {
+                sleep(timeout.min(FALLBACK_INTERVAL));
+                return Wakeup::Timeout;
+            }
+        };
+
+        let mut wakeup = Wakeup::Modified;
+        for event in std::iter::once(event).chain(self.events.try_iter()) {
+            match event {
+                Ok(event) if is_change(&event.kind) => wakeup = Wakeup::Changed,
+                Ok(_) => {}
+                Err(err) => debug!("tail watcher: {}", err),
+            }
+        }
+        wakeup
+    }
+}
+
+fn is_change(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Create(_)
+            | EventKind::Remove(_)
+            | EventKind::Modify(notify::event::ModifyKind::Name(_))
+    )
+}
+
+fn watch(
+    patterns: &[String],
+    tx: mpsc::Sender<notify::Result<Event>>,
+) -> notify::Result<RecommendedWatcher> {
+    let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
+    let dirs: BTreeSet<(PathBuf, bool)> = patterns.iter().map(|p| base_dir(p)).collect();
+    for (dir, recursive) in dirs {
+        let mode = if recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        // The safety poll still covers directories which can't be watched
+        match watcher.watch(&dir, mode) {
+            Ok(()) => debug!("watching {} for tailed files", dir.display()),
+            Err(err) => warn!("watching {}: {}", dir.display(), err),
+        }
+    }
+    Ok(watcher)
+}
+
+// The directory up to the first glob in the pattern, and whether the glob
+// reaches into its subdirectories.
+fn base_dir(pattern: &str) -> (PathBuf, bool) {
+    let path = Path::new(pattern);
+    let mut dir = PathBuf::new();
+    let mut components = path.parent().unwrap_or(Path::new("")).components();
+    for component in components.by_ref() {
+        if let Component::Normal(name) = component {
+            if name.to_string_lossy().contains(['*', '?', '[']) {
+                return (non_empty(dir), true);
+            }
+        }
+        dir.push(component);
+    }
+    (non_empty(dir), false)
+}
+
+fn non_empty(dir: PathBuf) -> PathBuf {
+    if dir.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        dir
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::base_dir;
+    use std::path::PathBuf;
+
+    #[test]
+    fn watches_up_to_the_first_glob() {
+        assert_eq!(
+            base_dir("/var/log/nginx/*.log"),
+            (PathBuf::from("/var/log/nginx"), false)
+        );
+        assert_eq!(
+            base_dir("/var/www/*/logs/access.log"),
+            (PathBuf::from("/var/www"), true)
+        );
+        assert_eq!(base_dir("access.log"), (PathBuf::from("."), false));
+    }
+}
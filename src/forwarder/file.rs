@@ -1,20 +1,27 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io;
 use std::os::unix::fs::MetadataExt;
+use std::path::Path;
 
 // Extend fs::File with convenience functions
 pub trait FileExtension {
-    fn was_rotated(&self, curr_inode: u64) -> Result<bool, io::Error>;
-    fn was_truncated(&self, curr_inode: u64, curr_pos: u64) -> Result<bool, io::Error>;
+    fn was_rotated(&self, path: &Path) -> Result<bool, io::Error>;
+    fn was_truncated(&self, curr_pos: u64) -> Result<bool, io::Error>;
 }
 
 impl FileExtension for File {
-    fn was_rotated(&self, curr_inode: u64) -> Result<bool, io::Error> {
-        Ok(self.metadata()?.ino() != curr_inode)
+    // The path now names another file than the open one. A path without a file
+    // yet, between the rename and the new file's creation, doesn't count.
+    fn was_rotated(&self, path: &Path) -> Result<bool, io::Error> {
+        let md = self.metadata()?;
+        match fs::metadata(path) {
+            Ok(current) => Ok(current.dev() != md.dev() || current.ino() != md.ino()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
     }
 
-    fn was_truncated(&self, curr_inode: u64, curr_pos: u64) -> Result<bool, io::Error> {
-        let md = self.metadata()?;
-        Ok(curr_inode == md.ino() && md.len() < curr_pos)
+    fn was_truncated(&self, curr_pos: u64) -> Result<bool, io::Error> {
+        Ok(self.metadata()?.len() < curr_pos)
     }
 }
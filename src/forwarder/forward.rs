@@ -0,0 +1,336 @@
+use crate::configuration;
+use crate::dry_run::{self, DryRunEvent};
+use crate::forwarder::parse::{Parsers, Record};
+use crate::forwarder::sink::Sink;
+use crate::forwarder::tails::Tails;
+use log::{debug, error, warn};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// Tail records waiting to be sent, up to the offset they end at in each file.
+#[derive(Default)]
+struct Batch {
+    records: Vec<Record>,
+    bytes: usize,
+    ends: BTreeMap<String, u64>,
+    started: Option<Instant>,
+}
+
+impl Batch {
+    fn push(&mut self, records: Vec<Record>, path: &str, end: u64, now: Instant) {
+        self.bytes += records
+            .iter()
+            .map(|record| record.data.len())
+            .sum::<usize>();
+        self.records.extend(records);
+        self.ends.insert(path.to_string(), end);
+        self.started.get_or_insert(now);
+    }
+
+    fn is_full(&self, limits: &configuration::Forwarder) -> bool {
+        self.bytes >= limits.max_batch_bytes || self.records.len() >= limits.max_batch_records
+    }
+
+    fn is_due(&self, now: Instant, limits: &configuration::Forwarder) -> bool {
+        let max_latency = Duration::from_millis(limits.max_latency_ms);
+        self.is_full(limits)
+            || self
+                .started
+                .is_some_and(|started| now >= started + max_latency)
+    }
+}
+
+// Reads the tails into batches and sends them to the sink. The tails'
+// checkpoints only move once the sink acknowledged a batch, a failed batch is
+// retried with backoff and nothing new is read while it's full.
+pub struct Forwarder {
+    tails: Arc<Mutex<Tails>>,
+    sink: Sink,
+    parsers: Parsers,
+    limits: configuration::Forwarder,
+    batch: Batch,
+    backoff: Duration,
+    retry_at: Option<Instant>,
+    dry_run: bool,
+}
+
+impl Forwarder {
+    pub fn new(
+        tails: Arc<Mutex<Tails>>,
+        sink: Sink,
+        limits: &configuration::Forwarder,
+        parsers: Parsers,
+        dry_run: bool,
+    ) -> Forwarder {
+        Forwarder {
+            tails,
+            sink,
+            parsers,
+            limits: limits.clone(),
+            batch: Batch::default(),
+            backoff: INITIAL_BACKOFF,
+            retry_at: None,
+            dry_run,
+        }
+    }
+
+    // Reads every tail once and sends the batch when it's due. True when there
+    // was new data, the caller may poll again right away.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        let mut read = false;
+        {
+            let mut tails = self.tails.lock().unwrap();
+            for tail in tails.iter_mut() {
+                if self.batch.is_full(&self.limits) {
+                    break;
+                }
+                match tail.next() {
+                    Ok(records) if !records.is_empty() => {
+                        let records =
+                            self.parsers
+                                .parse(tail.path(), records, OffsetDateTime::now_utc());
+                        self.batch.push(records, tail.path(), tail.position(), now);
+                        read = true;
+                    }
+                    Ok(_) => {}
+                    Err(err) => error!("tailing {}: {}", tail.path(), err),
+                }
+            }
+        }
+
+        let waiting = self.retry_at.is_some_and(|retry_at| now < retry_at);
+        if !waiting && self.batch.is_due(now, &self.limits) {
+            self.flush(now);
+        }
+        read
+    }
+
+    pub fn parse_errors(&self) -> u64 {
+        self.parsers.errors()
+    }
+
+    // When the batch is due by latency or its retry, if there's one.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        if self.retry_at.is_some() {
+            return self.retry_at;
+        }
+        let max_latency = Duration::from_millis(self.limits.max_latency_ms);
+        self.batch.started.map(|started| started + max_latency)
+    }
+
+    fn flush(&mut self, now: Instant) {
+        let outcome = if self.dry_run {
+            dry_run::report(&DryRunEvent::Forward {
+                url: self.sink.url(),
+                bytes: self.batch.bytes,
+                payload: self.sink.payload(&self.batch.records),
+            });
+            Ok(())
+        } else {
+            self.sink.send(&mut self.batch.records)
+        };
+
+        match outcome {
+            Ok(()) => {
+                debug!(
+                    "forwarded {} bytes, {} records",
+                    self.batch.bytes,
+                    self.batch.records.len()
+                );
+                self.acknowledge();
+            }
+            // Retrying would block the tails for good
+            Err(err) if !err.is_retryable() => {
+                error!(
+                    "dropping {} records for {}: {}",
+                    self.batch.records.len(),
+                    self.sink.url(),
+                    err
+                );
+                self.acknowledge();
+            }
+            Err(err) => {
+                // Only what the sink didn't accept is retried
+                self.batch.bytes = self
+                    .batch
+                    .records
+                    .iter()
+                    .map(|record| record.data.len())
+                    .sum();
+                warn!(
+                    "forwarding {} bytes to {}: {}, retrying in {:?}",
+                    self.batch.bytes,
+                    self.sink.url(),
+                    err,
+                    self.backoff
+                );
+                self.retry_at = Some(now + self.backoff);
+                self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+
+    // Moves the checkpoints past the batch and starts the next one.
+    fn acknowledge(&mut self) {
+        {
+            let mut tails = self.tails.lock().unwrap();
+            for (path, end) in &self.batch.ends {
+                if let Some(tail) = tails.get_mut(path) {
+                    tail.commit(*end);
+                }
+            }
+        }
+        self.batch = Batch::default();
+        self.backoff = INITIAL_BACKOFF;
+        self.retry_at = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Forwarder;
+    use crate::configuration;
+    use crate::forwarder::network::Transmitter;
+    use crate::forwarder::parse::Parsers;
+    use crate::forwarder::sink::Sink;
+    use crate::forwarder::tails::Tails;
+    use crate::testing::{stand_in, Request};
+    use serde_json::json;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::Path;
+    use std::sync::mpsc::Receiver;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    // Forwards to a collector answering with the next status, then 200.
+    fn forwarder(
+        tails: &Arc<Mutex<Tails>>,
+        limits: &configuration::Forwarder,
+        statuses: Vec<u16>,
+    ) -> (Forwarder, Receiver<Request>) {
+        let mut statuses = statuses.into_iter();
+        let (url, requests) = stand_in(move |_| (statuses.next().unwrap_or(200), String::new()));
+        let transmitter = Transmitter::new(&format!("{}/ingest", url), Duration::from_secs(5));
+        let sink = Sink::Http(transmitter.unwrap());
+        let parsers = Parsers::new(&[]).unwrap();
+        let forwarder = Forwarder::new(tails.clone(), sink, limits, parsers, false);
+        (forwarder, requests)
+    }
+
+    fn open_search_limits(dir: &Path, url: String) -> configuration::Forwarder {
+        configuration::Forwarder {
+            open_search: Some(configuration::OpenSearch {
+                url,
+                index: String::from("logga"),
+                auth: None,
+            }),
+            ..limits(dir, 2)
+        }
+    }
+
+    fn limits(dir: &Path, max_batch_records: usize) -> configuration::Forwarder {
+        configuration::Forwarder {
+            max_batch_records,
+            registry_path: dir.join("registry.json").display().to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn tails(dir: &Path, content: &str, limits: &configuration::Forwarder) -> Arc<Mutex<Tails>> {
+        let log = dir.join("access.log");
+        fs::write(&log, content).unwrap();
+        let tails = Tails::open(vec![log.display().to_string()], limits, &log).unwrap();
+        Arc::new(Mutex::new(tails))
+    }
+
+    fn checkpoint(tails: &Mutex<Tails>) -> u64 {
+        tails.lock().unwrap().checkpoints()[0].1
+    }
+
+    #[test]
+    fn checkpoint_follows_acknowledged_batches() {
+        let dir = tempfile::tempdir().unwrap();
+        let limits = limits(dir.path(), 2);
+        let tails = tails(dir.path(), "GET / 200\n", &limits);
+        let (mut forwarder, requests) = forwarder(&tails, &limits, vec![]);
+        let start = Instant::now();
+
+        // Neither full nor old enough
+        assert!(forwarder.poll(start));
+        assert_eq!(checkpoint(&tails), 0);
+
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(dir.path().join("access.log"))
+            .unwrap();
+        log.write_all(b"GET /a 404\n").unwrap();
+        forwarder.poll(start);
+        assert_eq!(requests.recv().unwrap().body, b"GET / 200\nGET /a 404\n");
+        assert_eq!(checkpoint(&tails), 21);
+
+        // A partial line isn't a record yet, latency flushes it once complete
+        log.write_all(b"GET /b").unwrap();
+        assert!(!forwarder.poll(start + Duration::from_secs(5)));
+        log.write_all(b" 200\n").unwrap();
+        forwarder.poll(start);
+        forwarder.poll(start + Duration::from_secs(1));
+        assert_eq!(requests.recv().unwrap().body, b"GET /b 200\n");
+        assert_eq!(checkpoint(&tails), 32);
+    }
+
+    #[test]
+    fn rejected_batch_is_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let limits = limits(dir.path(), 1);
+        let tails = tails(dir.path(), "GET / 200\n", &limits);
+        let (mut forwarder, requests) = forwarder(&tails, &limits, vec![503]);
+        let start = Instant::now();
+
+        forwarder.poll(start);
+        assert_eq!(requests.recv().unwrap().body, b"GET / 200\n");
+        assert_eq!(checkpoint(&tails), 0);
+
+        // Still backing off
+        forwarder.poll(start + Duration::from_millis(500));
+        assert_eq!(checkpoint(&tails), 0);
+
+        forwarder.poll(start + Duration::from_secs(1));
+        assert_eq!(requests.recv().unwrap().body, b"GET / 200\n");
+        assert_eq!(checkpoint(&tails), 10);
+    }
+
+    #[test]
+    fn checkpoint_waits_for_every_document() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut responses = vec![
+            json!({"errors": false, "items": [{"create": {"status": 201}}]}),
+            json!({"errors": true, "items": [
+                {"create": {"status": 201}},
+                {"create": {"status": 503}},
+            ]}),
+        ];
+        let (url, requests) = stand_in(move |_| (200, responses.pop().unwrap().to_string()));
+        let limits = open_search_limits(dir.path(), url);
+        let tails = tails(dir.path(), "GET / 200\nGET /a 404\n", &limits);
+        let sink = Sink::new(&limits).unwrap().unwrap();
+        let parsers = Parsers::new(&[]).unwrap();
+        let mut forwarder = Forwarder::new(tails.clone(), sink, &limits, parsers, false);
+        let start = Instant::now();
+
+        forwarder.poll(start);
+        requests.recv().unwrap();
+        assert_eq!(checkpoint(&tails), 0);
+
+        forwarder.poll(start + Duration::from_secs(1));
+        let request = requests.recv().unwrap();
+        assert!(String::from_utf8_lossy(&request.body).contains("GET /a 404"));
+        assert!(!String::from_utf8_lossy(&request.body).contains("GET / 200"));
+        assert_eq!(checkpoint(&tails), 21);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::configuration;
+use crate::forwarder::error::TailError;
+use std::mem;
+
+// Records cut out of one read, with the number of file bytes they covered.
+#[derive(Debug, Default, PartialEq)]
+pub struct Frames {
+    pub records: Vec<Vec<u8>>,
+    pub bytes: u64,
+}
+
+// Cuts reads into delimited records. The partial record at the end of a read is
+// carried over to the next one, so a record is only handed out once its
+// delimiter was read.
+pub struct Framer {
+    delimiter: u8,
+    max_record_bytes: usize,
+    truncation_marker: Vec<u8>,
+    // Start of the record being read, at most max_record_bytes long
+    partial: Vec<u8>,
+    // File bytes of the record being read, including the ones cut off
+    partial_bytes: u64,
+    truncated: bool,
+}
+
+impl Framer {
+    pub fn new(config: &configuration::Framing) -> Result<Framer, TailError> {
+        let delimiter = match config.delimiter.as_bytes() {
+            [delimiter] => *delimiter,
+            _ => return Err(TailError::InvalidDelimiter(config.delimiter.clone())),
+        };
+
+        Ok(Framer {
+            delimiter,
+            max_record_bytes: config.max_record_bytes,
+            truncation_marker: config.truncation_marker.as_bytes().to_vec(),
+            partial: vec![],
+            partial_bytes: 0,
+            truncated: false,
+        })
+    }
+
+    // Records completed by data, each ending with the delimiter.
+    pub fn push(&mut self, data: &[u8]) -> Frames {
+        let mut frames = Frames::default();
+        let mut rest = data;
+
+        while let Some(end) = rest.iter().position(|byte| *byte == self.delimiter) {
+            self.extend(&rest[..end]);
+            let mut record = mem::take(&mut self.partial);
+            if self.truncated {
+                record.extend_from_slice(&self.truncation_marker);
+            }
+            record.push(self.delimiter);
+
+            frames.records.push(record);
+            frames.bytes += self.partial_bytes + 1;
+            self.partial_bytes = 0;
+            self.truncated = false;
+            rest = &rest[end + 1..];
+        }
+        self.extend(rest);
+
+        frames
+    }
+
+    // The partial record as a last one, with the delimiter its file never got.
+    pub fn finish(&mut self) -> Frames {
+        if self.partial_bytes == 0 {
+            return Frames::default();
+        }
+        let mut frames = self.push(&[self.delimiter]);
+        frames.bytes -= 1;
+        frames
+    }
+
+    pub fn delimiter(&self) -> u8 {
+        self.delimiter
+    }
+
+    // File bytes read past the last complete record.
+    pub fn pending(&self) -> u64 {
+        self.partial_bytes
+    }
+
+    // Drops the partial record, the file it came from is gone.
+    pub fn reset(&mut self) {
+        self.partial.clear();
+        self.partial_bytes = 0;
+        self.truncated = false;
+    }
+
+    fn extend(&mut self, data: &[u8]) {
+        let room = self.max_record_bytes.saturating_sub(self.partial.len());
+        if data.len() > room {
+            self.partial.extend_from_slice(&data[..room]);
+            self.truncated = true;
+        } else {
+            self.partial.extend_from_slice(data);
+        }
+        self.partial_bytes += data.len() as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Framer;
+    use crate::configuration::Framing;
+
+    fn framer(delimiter: &str, max_record_bytes: usize) -> Framer {
+        Framer::new(&Framing {
+            delimiter: String::from(delimiter),
+            max_record_bytes,
+            truncation_marker: String::from("[truncated]"),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn carries_partial_records_over() {
+        let mut framer = framer("\n", 100);
+
+        let frames = framer.push(b"GET / 200\nGET /fav");
+        assert_eq!(frames.records, vec![b"GET / 200\n".to_vec()]);
+        assert_eq!(frames.bytes, 10);
+        assert_eq!(framer.pending(), 8);
+
+        assert!(framer.push(b"icon.ico").records.is_empty());
+        let frames = framer.push(b" 404\nPOST");
+        assert_eq!(frames.records, vec![b"GET /favicon.ico 404\n".to_vec()]);
+        assert_eq!(frames.bytes, 21);
+        assert_eq!(framer.pending(), 4);
+    }
+
+    #[test]
+    fn truncates_long_records() {
+        let mut framer = framer("\0", 4);
+
+        assert!(framer.push(b"abcdefgh").records.is_empty());
+        let frames = framer.push(b"ij\0ok\0");
+        assert_eq!(
+            frames.records,
+            vec![b"abcd[truncated]\0".to_vec(), b"ok\0".to_vec()]
+        );
+        // The cut off bytes count, the next record starts after them
+        assert_eq!(frames.bytes, 14);
+        assert_eq!(framer.pending(), 0);
+
+        assert!(Framer::new(&Framing {
+            delimiter: String::from("\r\n"),
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,326 @@
+use crate::configuration::{self, LokiEncoding};
+use crate::forwarder::error::TransmitError;
+use crate::forwarder::network::Transmitter;
+use crate::forwarder::parse::{self, Record};
+use crate::uploader::upload;
+use prost::Message;
+use reqwest::header::CONTENT_TYPE;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+const PUSH_PATH: &str = "/loki/api/v1/push";
+
+// The push API's protobuf messages, logproto's PushRequest.
+#[derive(Clone, PartialEq, Message)]
+pub struct PushRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub streams: Vec<Stream>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Stream {
+    // e.g. {filename="/var/log/nginx/access.log", host="web-01"}
+    #[prost(string, tag = "1")]
+    pub labels: String,
+    #[prost(message, repeated, tag = "2")]
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Entry {
+    #[prost(message, optional, tag = "1")]
+    pub timestamp: Option<Timestamp>,
+    #[prost(string, tag = "2")]
+    pub line: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Timestamp {
+    #[prost(int64, tag = "1")]
+    pub seconds: i64,
+    #[prost(int32, tag = "2")]
+    pub nanos: i32,
+}
+
+type Labels = BTreeMap<String, String>;
+
+// Pushes batches to Loki, one stream per tailed file with its entries in
+// timestamp order.
+pub struct Loki {
+    transmitter: Transmitter,
+    labels: Labels,
+    encoding: LokiEncoding,
+}
+
+impl Loki {
+    pub fn new(config: &configuration::Loki, timeout: Duration) -> Result<Loki, TransmitError> {
+        let url = format!("{}{}", config.url.trim_end_matches('/'), PUSH_PATH);
+        let mut labels = Labels::from([(String::from("host"), upload::hostname())]);
+        labels.extend(config.labels.clone());
+
+        Ok(Loki {
+            transmitter: Transmitter::new(&url, timeout)?,
+            labels,
+            encoding: config.encoding,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        self.transmitter.url()
+    }
+
+    // Entries Loki refused (400) are dropped, they never succeed. Pushes too large
+    // for it (413) are split, other failures retried. On failure records holds
+    // what's left to push.
+    pub fn push(&self, records: &mut Vec<Record>) -> Result<(), TransmitError> {
+        match self.push_all(records) {
+            Err(TransmitError::Status(413)) if records.len() > 1 => {
+                let mut rest = records.split_off(records.len() / 2);
+                let outcome = self.push(records);
+                if outcome.is_err() {
+                    records.append(&mut rest);
+                    return outcome;
+                }
+                let outcome = self.push(&mut rest);
+                *records = rest;
+                outcome
+            }
+            Err(TransmitError::Status(413)) => Err(TransmitError::Rejected(
+                413,
+                String::from("a single entry exceeds Loki's request size limit"),
+            )),
+            outcome => outcome,
+        }
+    }
+
+    fn push_all(&self, records: &[Record]) -> Result<(), TransmitError> {
+        let (body, content_type) = match self.encoding {
+            LokiEncoding::Protobuf => (self.protobuf(records)?, "application/x-protobuf"),
+            LokiEncoding::Json => (
+                self.json(records).to_string().into_bytes(),
+                "application/json",
+            ),
+        };
+
+        let resp = self
+            .transmitter
+            .request()
+            .header(CONTENT_TYPE, content_type)
+            .body(body)
+            .send()
+            .map_err(TransmitError::Request)?;
+        match resp.status().as_u16() {
+            200..=299 => Ok(()),
+            400 => Err(TransmitError::Rejected(
+                400,
+                resp.text().unwrap_or_default().trim().to_string(),
+            )),
+            // Throttling, server errors, but also wrong credentials or URLs, which
+            // an operator fixes without losing the logs
+            status => Err(TransmitError::Status(status)),
+        }
+    }
+
+    // The push as JSON.
+    pub fn json(&self, records: &[Record]) -> Value {
+        let streams: Vec<Value> = self
+            .streams(records)
+            .into_iter()
+            .map(|(labels, records)| {
+                let values: Vec<Value> = records
+                    .iter()
+                    .map(|record| {
+                        json!([
+                            record.timestamp.unix_timestamp_nanos().to_string(),
+                            parse::line(&record.data)
+                        ])
+                    })
+                    .collect();
+                json!({"stream": labels, "values": values})
+            })
+            .collect();
+        json!({ "streams": streams })
+    }
+
+    fn protobuf(&self, records: &[Record]) -> Result<Vec<u8>, TransmitError> {
+        let streams = self
+            .streams(records)
+            .into_iter()
+            .map(|(labels, records)| Stream {
+                labels: render(&labels),
+                entries: records
+                    .iter()
+                    .map(|record| Entry {
+                        timestamp: Some(Timestamp {
+                            seconds: record.timestamp.unix_timestamp(),
+                            nanos: record.timestamp.nanosecond() as i32,
+                        }),
+                        line: parse::line(&record.data),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        snap::raw::Encoder::new()
+            .compress_vec(&PushRequest { streams }.encode_to_vec())
+            .map_err(|err| TransmitError::Encode(err.to_string()))
+    }
+
+    // Records grouped by their labels. Sorting keeps records with the same
+    // timestamp in the order they were read.
+    fn streams<'a>(&self, records: &'a [Record]) -> BTreeMap<Labels, Vec<&'a Record>> {
+        let mut streams: BTreeMap<Labels, Vec<&Record>> = BTreeMap::new();
+        for record in records {
+            let mut labels = Labels::from([(String::from("filename"), record.path.clone())]);
+            labels.extend(self.labels.clone());
+            streams.entry(labels).or_default().push(record);
+        }
+        for records in streams.values_mut() {
+            records.sort_by_key(|record| record.timestamp);
+        }
+        streams
+    }
+}
+
+// Loki's label selector syntax.
+fn render(labels: &Labels) -> String {
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!("{}={:?}", name, value))
+        .collect();
+    format!("{{{}}}", pairs.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Loki, PushRequest};
+    use crate::configuration::{self, LokiEncoding};
+    use crate::forwarder::parse::Record;
+    use crate::testing::stand_in;
+    use prost::Message;
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+    use time::macros::datetime;
+
+    fn loki(url: &str, encoding: LokiEncoding) -> Loki {
+        let config = configuration::Loki {
+            url: url.to_string(),
+            labels: BTreeMap::from([
+                (String::from("job"), String::from("nginx")),
+                (String::from("host"), String::from("web-01")),
+            ]),
+            encoding,
+        };
+        Loki::new(&config, Duration::from_secs(5)).unwrap()
+    }
+
+    fn records() -> Vec<Record> {
+        let record = |path: &str, data: &[u8], timestamp| Record {
+            path: path.to_string(),
+            data: data.to_vec(),
+            timestamp,
+        };
+        vec![
+            record(
+                "/var/log/a.log",
+                b"GET /2 200\n",
+                datetime!(2026-10-18 10:00:02 UTC),
+            ),
+            record(
+                "/var/log/b.log",
+                b"GET /b 404\n",
+                datetime!(2026-10-18 10:00:03 UTC),
+            ),
+            record(
+                "/var/log/a.log",
+                b"GET /1 200\n",
+                datetime!(2026-10-18 10:00:01.5 UTC),
+            ),
+        ]
+    }
+
+    #[test]
+    fn pushes_ordered_streams() {
+        let (url, requests) = stand_in(|_| (204, String::new()));
+
+        loki(&url, LokiEncoding::Protobuf)
+            .push(&mut records())
+            .unwrap();
+        let request = requests.recv().unwrap();
+        assert_eq!(request.path, "/loki/api/v1/push");
+        assert_eq!(request.headers["content-type"], "application/x-protobuf");
+        let body = snap::raw::Decoder::new()
+            .decompress_vec(&request.body)
+            .unwrap();
+        let push = PushRequest::decode(body.as_slice()).unwrap();
+
+        assert_eq!(push.streams.len(), 2);
+        let stream = &push.streams[0];
+        assert_eq!(
+            stream.labels,
+            r#"{filename="/var/log/a.log", host="web-01", job="nginx"}"#
+        );
+        let lines: Vec<&str> = stream.entries.iter().map(|e| e.line.as_str()).collect();
+        assert_eq!(lines, ["GET /1 200", "GET /2 200"]);
+        let timestamp = stream.entries[0].timestamp.as_ref().unwrap();
+        assert_eq!(
+            (timestamp.seconds, timestamp.nanos),
+            (1792317601, 500_000_000)
+        );
+
+        loki(&url, LokiEncoding::Json).push(&mut records()).unwrap();
+        let request = requests.recv().unwrap();
+        let push: Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(
+            push["streams"][1],
+            json!({
+                "stream": {"filename": "/var/log/b.log", "host": "web-01", "job": "nginx"},
+                "values": [["1792317603000000000", "GET /b 404"]],
+            })
+        );
+    }
+
+    #[test]
+    fn drops_only_refused_entries() {
+        let mut statuses = vec![400, 404, 403, 401, 503, 429].into_iter();
+        let (url, _requests) = stand_in(move |_| {
+            let status = statuses.next_back().unwrap();
+            (status, String::from("entry too far behind"))
+        });
+        let loki = loki(&url, LokiEncoding::Json);
+
+        for retryable in [true, true, true, true, true, false] {
+            let err = loki.push(&mut records()).unwrap_err();
+            assert_eq!(err.is_retryable(), retryable, "{}", err);
+        }
+    }
+
+    fn entries(body: &[u8]) -> usize {
+        let push: Value = serde_json::from_slice(body).unwrap();
+        push["streams"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|stream| stream["values"].as_array().unwrap().len())
+            .sum()
+    }
+
+    #[test]
+    fn splits_pushes_too_large() {
+        // Loki takes a single entry at a time
+        let (url, requests) = stand_in(|request| match entries(&request.body) {
+            1 => (204, String::new()),
+            _ => (413, String::from("request too large")),
+        });
+        let loki = loki(&url, LokiEncoding::Json);
+
+        loki.push(&mut records()).unwrap();
+        let pushed: Vec<usize> = requests
+            .try_iter()
+            .map(|request| entries(&request.body))
+            .collect();
+        assert_eq!(pushed, [3, 1, 2, 1, 1]);
+    }
+}
@@ -1,4 +1,14 @@
+mod checkpoint;
 pub mod error;
+pub mod events;
 mod file;
+pub mod forward;
+mod framing;
+mod loki;
 pub mod network;
+mod opensearch;
+pub mod parse;
+mod registry;
+pub mod sink;
 pub mod tail;
+pub mod tails;
@@ -1,21 +1,44 @@
-use std::error::Error;
+use crate::forwarder::error::TransmitError;
+use std::time::Duration;
 
-pub struct Transmitter<'a> {
-    client: reqwest::Client,
-    url: &'a str,
+pub struct Transmitter {
+    client: reqwest::blocking::Client,
+    url: String,
 }
 
-impl Transmitter<'_> {
-    pub fn new(url: &str) -> Transmitter {
-        Transmitter {
-            url,
-            client: reqwest::Client::new(),
-        }
+impl Transmitter {
+    pub fn new(url: &str, timeout: Duration) -> Result<Transmitter, TransmitError> {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(TransmitError::Request)?;
+
+        Ok(Transmitter {
+            client,
+            url: url.to_string(),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
     }
 
-    pub async fn transmit_data_chunk(&self, data: Vec<u8>) -> Result<(), Box<dyn Error>> {
-        let resp = self.client.post(self.url).body(data).send().await;
-        println!("{:?}", resp);
+    // POSTs a batch, Ok once the server acknowledged it with a 2xx status.
+    pub fn transmit_data_chunk(&self, data: Vec<u8>) -> Result<(), TransmitError> {
+        let resp = self
+            .request()
+            .body(data)
+            .send()
+            .map_err(TransmitError::Request)?;
+
+        if !resp.status().is_success() {
+            return Err(TransmitError::Status(resp.status().as_u16()));
+        }
         Ok(())
     }
+
+    // A POST to the url, for sinks adding their own headers.
+    pub fn request(&self) -> reqwest::blocking::RequestBuilder {
+        self.client.post(&self.url)
+    }
 }
@@ -0,0 +1,286 @@
+use crate::configuration::{self, OpenSearchAuth};
+use crate::forwarder::error::TransmitError;
+use crate::forwarder::network::Transmitter;
+use crate::forwarder::parse::{self, Record};
+use crate::template::{Template, TemplateContext};
+use crate::uploader::upload;
+use log::error;
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+use time::format_description::well_known::Rfc3339;
+use time::UtcOffset;
+
+const BULK_PATH: &str = "/_bulk";
+
+#[derive(Deserialize)]
+struct BulkResponse {
+    errors: bool,
+    // One per document, keyed by the action
+    #[serde(default)]
+    items: Vec<BTreeMap<String, BulkItem>>,
+}
+
+#[derive(Deserialize)]
+struct BulkItem {
+    status: u16,
+    #[serde(default)]
+    error: Option<Value>,
+}
+
+// Indexes batches with the bulk API. Parsed records are indexed as they are,
+// raw lines as {"message": line}, both with @timestamp, hostname and filename.
+pub struct OpenSearch {
+    transmitter: Transmitter,
+    index: Template,
+    auth: Option<OpenSearchAuth>,
+    host: String,
+}
+
+impl OpenSearch {
+    pub fn new(
+        config: &configuration::OpenSearch,
+        timeout: Duration,
+    ) -> Result<OpenSearch, TransmitError> {
+        let url = format!("{}{}", config.url.trim_end_matches('/'), BULK_PATH);
+        Ok(OpenSearch {
+            transmitter: Transmitter::new(&url, timeout)?,
+            index: Template::parse(&config.index).map_err(TransmitError::Index)?,
+            auth: config.auth.clone(),
+            host: upload::hostname(),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        self.transmitter.url()
+    }
+
+    // Leaves the records whose documents are worth retrying, those the cluster
+    // was too busy for. Documents it refused for good are dropped.
+    pub fn bulk(&self, records: &mut Vec<Record>) -> Result<(), TransmitError> {
+        let mut request = self
+            .transmitter
+            .request()
+            .header(CONTENT_TYPE, "application/x-ndjson")
+            .body(self.body(records));
+        request = match &self.auth {
+            Some(OpenSearchAuth::Basic { username, password }) => {
+                request.basic_auth(username, Some(password))
+            }
+            Some(OpenSearchAuth::ApiKey(key)) => {
+                request.header(AUTHORIZATION, format!("ApiKey {}", key))
+            }
+            None => request,
+        };
+
+        let resp = request.send().map_err(TransmitError::Request)?;
+        if !resp.status().is_success() {
+            return Err(TransmitError::Status(resp.status().as_u16()));
+        }
+        let body = resp.bytes().map_err(TransmitError::Request)?;
+        let response: BulkResponse = serde_json::from_slice(&body)
+            .map_err(|err| TransmitError::UnexpectedResponse(err.to_string()))?;
+        if !response.errors {
+            return Ok(());
+        }
+        if response.items.len() != records.len() {
+            return Err(TransmitError::UnexpectedResponse(format!(
+                "{} items for {} documents",
+                response.items.len(),
+                records.len()
+            )));
+        }
+
+        let of = records.len();
+        let mut retry = vec![];
+        let mut retry_reason = None;
+        let mut dropped = 0;
+        let mut drop_reason = None;
+        for (record, item) in records.drain(..).zip(response.items) {
+            let Some(item) = item.into_values().next() else {
+                retry.push(record);
+                continue;
+            };
+            let reason = || item.error.clone().unwrap_or_default().to_string();
+            match item.status {
+                200..=299 => {}
+                429 | 500.. => {
+                    retry_reason.get_or_insert_with(reason);
+                    retry.push(record);
+                }
+                _ => {
+                    drop_reason.get_or_insert_with(reason);
+                    dropped += 1;
+                }
+            }
+        }
+        if let Some(reason) = drop_reason {
+            error!(
+                "dropping {} documents {} refused: {}",
+                dropped,
+                self.url(),
+                reason
+            );
+        }
+
+        *records = retry;
+        if records.is_empty() {
+            return Ok(());
+        }
+        Err(TransmitError::Documents {
+            failed: records.len(),
+            of,
+            reason: retry_reason.unwrap_or_default(),
+        })
+    }
+
+    // The bulk request's NDJSON, an action line followed by each document.
+    pub fn body(&self, records: &[Record]) -> Vec<u8> {
+        let mut body = vec![];
+        for record in records {
+            let timestamp = record.timestamp.to_offset(UtcOffset::UTC);
+            let filename = Path::new(&record.path)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            // OpenSearch refuses index names with uppercase letters, e.g. from {host}
+            let index = self
+                .index
+                .render(&TemplateContext {
+                    host: &self.host,
+                    filename: &filename,
+                    time: timestamp,
+                })
+                .to_lowercase();
+
+            let mut document = match serde_json::from_slice(&record.data) {
+                Ok(Value::Object(fields)) => fields,
+                _ => Map::from_iter([(
+                    String::from("message"),
+                    Value::from(parse::line(&record.data)),
+                )]),
+            };
+            document
+                .entry("@timestamp")
+                .or_insert_with(|| Value::from(timestamp.format(&Rfc3339).unwrap_or_default()));
+            document
+                .entry("hostname")
+                .or_insert_with(|| Value::from(self.host.as_str()));
+            document
+                .entry("filename")
+                .or_insert_with(|| Value::from(record.path.as_str()));
+
+            body.extend(
+                json!({"create": {"_index": index}})
+                    .to_string()
+                    .into_bytes(),
+            );
+            body.push(b'\n');
+            body.extend(Value::Object(document).to_string().into_bytes());
+            body.push(b'\n');
+        }
+        body
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OpenSearch;
+    use crate::configuration::{self, OpenSearchAuth};
+    use crate::forwarder::error::TransmitError;
+    use crate::forwarder::parse::Record;
+    use crate::testing::stand_in;
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use time::macros::datetime;
+
+    #[test]
+    fn retries_only_failed_documents() {
+        let mut responses = vec![
+            json!({"errors": false, "items": [{"create": {"status": 201}}]}),
+            json!({"errors": true, "items": [
+                {"create": {"status": 201}},
+                {"create": {"status": 429, "error": {"type": "es_rejected_execution_exception"}}},
+                {"create": {"status": 400, "error": {"type": "mapper_parsing_exception"}}},
+            ]}),
+        ];
+        let (url, requests) = stand_in(move |_| (200, responses.pop().unwrap().to_string()));
+        let config = configuration::OpenSearch {
+            url,
+            index: String::from("logga-{yyyy.MM.dd}"),
+            auth: Some(OpenSearchAuth::Basic {
+                username: String::from("user"),
+                password: String::from("pass"),
+            }),
+        };
+        let opensearch = OpenSearch::new(&config, Duration::from_secs(5)).unwrap();
+
+        let record = |data: &[u8]| Record {
+            path: String::from("/var/log/nginx/access.log"),
+            data: data.to_vec(),
+            timestamp: datetime!(2026-10-18 01:30 +02:00),
+        };
+        let mut records = vec![
+            record(b"GET / 200\n"),
+            record(b"{\"status\":503,\"hostname\":\"web-01\"}\n"),
+            record(b"{\"status\":\"x\"}\n"),
+        ];
+        match opensearch.bulk(&mut records) {
+            Err(TransmitError::Documents {
+                failed: 1, of: 3, ..
+            }) => {}
+            outcome => panic!("expected one failed document, got {:?}", outcome),
+        }
+        assert_eq!(
+            records,
+            vec![record(b"{\"status\":503,\"hostname\":\"web-01\"}\n")]
+        );
+
+        let request = requests.recv().unwrap();
+        assert_eq!(request.path, "/_bulk");
+        assert_eq!(request.headers["authorization"], "Basic dXNlcjpwYXNz");
+        let lines: Vec<Value> = request
+            .body
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], json!({"create": {"_index": "logga-2026.10.17"}}));
+        assert_eq!(lines[1]["message"], "GET / 200");
+        assert_eq!(lines[1]["@timestamp"], "2026-10-17T23:30:00Z");
+        assert_eq!(lines[1]["filename"], "/var/log/nginx/access.log");
+        assert_eq!(lines[3]["hostname"], "web-01");
+
+        // Only the failed document is sent again
+        opensearch.bulk(&mut records).unwrap();
+        let request = requests.recv().unwrap();
+        assert_eq!(request.body.split(|byte| *byte == b'\n').count(), 3);
+    }
+
+    #[test]
+    fn lowercases_index() {
+        let config = configuration::OpenSearch {
+            url: String::from("http://localhost:9200"),
+            index: String::from("Logga-{host}-{yyyy.MM}"),
+            auth: None,
+        };
+        let mut opensearch = OpenSearch::new(&config, Duration::from_secs(5)).unwrap();
+        opensearch.host = String::from("Mac-01.local");
+
+        let body = opensearch.body(&[Record {
+            path: String::from("/var/log/nginx/access.log"),
+            data: b"GET / 200\n".to_vec(),
+            timestamp: datetime!(2026-10-18 01:30 UTC),
+        }]);
+        let action: Value =
+            serde_json::from_slice(body.split(|byte| *byte == b'\n').next().unwrap()).unwrap();
+        assert_eq!(
+            action,
+            json!({"create": {"_index": "logga-mac-01.local-2026.10"}})
+        );
+    }
+}
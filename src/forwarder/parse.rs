@@ -0,0 +1,403 @@
+use crate::configuration::{self, LogFormat};
+use crate::forwarder::error::ParserError;
+use glob::Pattern;
+use log::{debug, warn};
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+// A request line that isn't one, e.g. "-" for a client closing early, leaves
+// method, path and protocol out.
+const COMMON: &str = r#"^(?P<host>\S+) (?P<ident>\S+) (?P<user>\S+) \[(?P<timestamp>[^\]]+)\] "(?:(?P<method>\S+) (?P<path>\S+) (?P<protocol>[^"]+)|[^"]*)" (?P<status>\d{3}) (?P<bytes>\d+|-)"#;
+const COMBINED: &str = r#" "(?P<referer>[^"]*)" "(?P<userAgent>[^"]*)""#;
+
+// None when the value has the wrong type
+type Convert = fn(&Value) -> Option<Value>;
+
+// Converted from text, a line with one that doesn't convert fails to parse.
+const TYPED_FIELDS: [(&str, Convert); 4] = [
+    ("timestamp", timestamp),
+    ("status", status),
+    ("bytes", bytes),
+    ("latency", latency),
+];
+
+// A line ready to be forwarded.
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    // The tailed file
+    pub path: String,
+    // Ends with the delimiter, or a newline when parsed
+    pub data: Vec<u8>,
+    // The parsed timestamp, or else when the line was read
+    pub timestamp: OffsetDateTime,
+}
+
+enum Format {
+    // Named captures become fields, "-" leaves them out
+    Regex(Regex),
+    Json,
+    Logfmt,
+}
+
+struct Rule {
+    path: Pattern,
+    format: Format,
+}
+
+// Turns the lines of the tailed files into JSON records by the first parser
+// matching their path. Lines failing to parse are still forwarded, as
+// {"message": line, "parseError": reason}, and counted per file.
+pub struct Parsers {
+    rules: Vec<Rule>,
+    // Index of the rule for each tailed file, None forwards it as it is
+    selected: BTreeMap<String, Option<usize>>,
+    errors: BTreeMap<String, u64>,
+}
+
+impl Parsers {
+    pub fn new(config: &[configuration::Parser]) -> Result<Parsers, ParserError> {
+        let mut rules = vec![];
+        for parser in config {
+            let path = Pattern::new(&parser.path)
+                .map_err(|err| ParserError::Pattern(parser.path.clone(), err))?;
+            let regex = |pattern: &str| {
+                Regex::new(pattern)
+                    .map(Format::Regex)
+                    .map_err(|err| ParserError::Regex(parser.path.clone(), err))
+            };
+            let format = match (parser.format, &parser.pattern) {
+                (LogFormat::Common, _) => regex(&format!("{}$", COMMON))?,
+                (LogFormat::Combined, _) => regex(&format!("{}{}$", COMMON, COMBINED))?,
+                (LogFormat::Json, _) => Format::Json,
+                (LogFormat::Logfmt, _) => Format::Logfmt,
+                (LogFormat::Regex, Some(pattern)) => regex(pattern)?,
+                (LogFormat::Regex, None) => {
+                    return Err(ParserError::MissingRegex(parser.path.clone()))
+                }
+            };
+            rules.push(Rule { path, format });
+        }
+
+        Ok(Parsers {
+            rules,
+            selected: BTreeMap::new(),
+            errors: BTreeMap::new(),
+        })
+    }
+
+    // One JSON line per record, records of files without a parser stay as they are.
+    pub fn parse(
+        &mut self,
+        path: &str,
+        records: Vec<Vec<u8>>,
+        read_at: OffsetDateTime,
+    ) -> Vec<Record> {
+        let rules = &self.rules;
+        let selected = *self
+            .selected
+            .entry(path.to_string())
+            .or_insert_with(|| rules.iter().position(|rule| rule.path.matches(path)));
+        let Some(index) = selected else {
+            return records
+                .into_iter()
+                .map(|data| Record {
+                    path: path.to_string(),
+                    data,
+                    timestamp: read_at,
+                })
+                .collect();
+        };
+
+        records
+            .iter()
+            .map(|record| {
+                let line = line(record);
+                let fields = match self.rules[index].format.parse(&line) {
+                    Ok(fields) => fields,
+                    Err(reason) => {
+                        let errors = self.errors.entry(path.to_string()).or_default();
+                        *errors += 1;
+                        if *errors == 1 {
+                            warn!("parsing {}: {}, further errors are counted", path, reason);
+                        } else {
+                            debug!("parsing {} ({} errors): {}", path, errors, reason);
+                        }
+                        let mut fields = Map::new();
+                        fields.insert(String::from("message"), Value::String(line));
+                        fields.insert(String::from("parseError"), Value::String(reason));
+                        fields
+                    }
+                };
+                let timestamp = fields
+                    .get("timestamp")
+                    .and_then(Value::as_str)
+                    .and_then(|timestamp| OffsetDateTime::parse(timestamp, &Rfc3339).ok())
+                    .unwrap_or(read_at);
+                let mut data = Value::Object(fields).to_string().into_bytes();
+                data.push(b'\n');
+                Record {
+                    path: path.to_string(),
+                    data,
+                    timestamp,
+                }
+            })
+            .collect()
+    }
+
+    // Lines which failed to parse, over all files.
+    pub fn errors(&self) -> u64 {
+        self.errors.values().sum()
+    }
+}
+
+impl Format {
+    fn parse(&self, line: &str) -> Result<Map<String, Value>, String> {
+        let mut fields = match self {
+            Format::Regex(regex) => {
+                let captures = regex
+                    .captures(line)
+                    .ok_or_else(|| String::from("doesn't match the format"))?;
+                let mut fields = Map::new();
+                for name in regex.capture_names().flatten() {
+                    match captures.name(name) {
+                        Some(value) if value.as_str() != "-" => {
+                            fields.insert(name.to_string(), Value::from(value.as_str()));
+                        }
+                        _ => {}
+                    }
+                }
+                fields
+            }
+            Format::Json => match serde_json::from_str(line) {
+                Ok(Value::Object(fields)) => fields,
+                Ok(_) => return Err(String::from("not a JSON object")),
+                Err(err) => return Err(err.to_string()),
+            },
+            Format::Logfmt => logfmt(line)?,
+        };
+
+        for (name, convert) in TYPED_FIELDS {
+            if let Some(value) = fields.get_mut(name) {
+                *value = convert(value).ok_or_else(|| format!("invalid {} {}", name, value))?;
+            }
+        }
+        Ok(fields)
+    }
+}
+
+// The record without its delimiter, nor a carriage return before it.
+pub fn line(record: &[u8]) -> String {
+    let line = &record[..record.len().saturating_sub(1)];
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    String::from_utf8_lossy(line).to_string()
+}
+
+// key=value pairs separated by whitespace. Values may be quoted with
+// backslash escapes, a key without a value is true.
+fn logfmt(line: &str) -> Result<Map<String, Value>, String> {
+    let mut fields = Map::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '=' && *c != '"') {
+            key.push(c);
+        }
+        if key.is_empty() {
+            return Err(String::from("expected a key"));
+        }
+        if chars.next_if_eq(&'=').is_none() {
+            fields.insert(key, Value::Bool(true));
+            continue;
+        }
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some(c) => value.push(c),
+                        None => return Err(format!("unterminated value of {}", key)),
+                    },
+                    Some(c) => value.push(c),
+                    None => return Err(format!("unterminated value of {}", key)),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                value.push(c);
+            }
+        }
+        fields.insert(key, Value::String(value));
+    }
+
+    if fields.is_empty() {
+        return Err(String::from("no key=value pairs"));
+    }
+    Ok(fields)
+}
+
+// RFC 3339, from the Common Log Format's, RFC 3339 or seconds since the epoch.
+fn timestamp(value: &Value) -> Option<Value> {
+    let clf = format_description!(
+        "[day]/[month repr:short]/[year]:[hour]:[minute]:[second] [offset_hour sign:mandatory][offset_minute]"
+    );
+    let at = match value {
+        Value::String(text) => OffsetDateTime::parse(text, clf)
+            .or_else(|_| OffsetDateTime::parse(text, &Rfc3339))
+            .ok()?,
+        Value::Number(seconds) => {
+            OffsetDateTime::from_unix_timestamp_nanos((seconds.as_f64()? * 1e9) as i128).ok()?
+        }
+        _ => return None,
+    };
+    at.format(&Rfc3339).ok().map(Value::String)
+}
+
+fn status(value: &Value) -> Option<Value> {
+    let status = match value {
+        Value::String(text) => text.parse().ok()?,
+        Value::Number(status) => u16::try_from(status.as_u64()?).ok()?,
+        _ => return None,
+    };
+    (100..1000).contains(&status).then(|| Value::from(status))
+}
+
+fn bytes(value: &Value) -> Option<Value> {
+    match value {
+        Value::String(text) => text.parse::<u64>().ok().map(Value::from),
+        Value::Number(bytes) => bytes.as_u64().map(Value::from),
+        _ => None,
+    }
+}
+
+// Seconds, e.g. nginx's $request_time
+fn latency(value: &Value) -> Option<Value> {
+    let latency = match value {
+        Value::String(text) => text.parse::<f64>().ok()?,
+        Value::Number(latency) => latency.as_f64()?,
+        _ => return None,
+    };
+    (latency.is_finite() && latency >= 0.0).then(|| Value::from(latency))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Parsers;
+    use crate::configuration::{LogFormat, Parser};
+    use serde_json::{json, Value};
+    use time::OffsetDateTime;
+
+    fn parse(format: LogFormat, pattern: Option<&str>, line: &str) -> Value {
+        let mut parsers = Parsers::new(&[Parser {
+            path: String::from("/var/log/*.log"),
+            format,
+            pattern: pattern.map(String::from),
+        }])
+        .unwrap();
+        let lines = vec![line.as_bytes().to_vec()];
+        let records = parsers.parse("/var/log/access.log", lines, OffsetDateTime::now_utc());
+        serde_json::from_slice(&records[0].data).unwrap()
+    }
+
+    #[test]
+    fn parses_access_log_formats() {
+        let common =
+            "127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /a.gif HTTP/1.0\" 200 2326\n";
+        assert_eq!(
+            parse(LogFormat::Common, None, common),
+            json!({
+                "host": "127.0.0.1",
+                "user": "frank",
+                "timestamp": "2000-10-10T13:55:36-07:00",
+                "method": "GET",
+                "path": "/a.gif",
+                "protocol": "HTTP/1.0",
+                "status": 200,
+                "bytes": 2326,
+            })
+        );
+
+        let combined =
+            "10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"-\" 408 - \"-\" \"curl/8.4.0\"\n";
+        assert_eq!(
+            parse(LogFormat::Combined, None, combined),
+            json!({
+                "host": "10.0.0.1",
+                "timestamp": "2000-10-10T13:55:36Z",
+                "status": 408,
+                "userAgent": "curl/8.4.0",
+            })
+        );
+
+        let line = "{\"status\":\"503\",\"latency\":0.25,\"timestamp\":971186136}\n";
+        assert_eq!(
+            parse(LogFormat::Json, None, line),
+            json!({"status": 503, "latency": 0.25, "timestamp": "2000-10-10T13:55:36Z"})
+        );
+
+        let line = "level=warn msg=\"slow \\\"upstream\\\"\" latency=1.5 cached\r\n";
+        assert_eq!(
+            parse(LogFormat::Logfmt, None, line),
+            json!({"level": "warn", "msg": "slow \"upstream\"", "latency": 1.5, "cached": true})
+        );
+
+        let pattern = r"^(?P<method>\w+) (?P<path>\S+) (?P<status>\d+) (?P<latency>\S+)$";
+        assert_eq!(
+            parse(LogFormat::Regex, Some(pattern), "GET / 200 0.003\n"),
+            json!({"method": "GET", "path": "/", "status": 200, "latency": 0.003})
+        );
+    }
+
+    #[test]
+    fn counts_lines_failing_to_parse() {
+        let mut parsers = Parsers::new(&[Parser {
+            path: String::from("/var/log/app/*.log"),
+            format: LogFormat::Logfmt,
+            pattern: None,
+        }])
+        .unwrap();
+
+        let lines = vec![
+            b"status=200\n".to_vec(),
+            b"status=ok\n".to_vec(),
+            b"msg=\"cut\n".to_vec(),
+        ];
+        let read_at = OffsetDateTime::now_utc();
+        let records = parsers.parse("/var/log/app/api.log", lines, read_at);
+        assert_eq!(records[1].timestamp, read_at);
+        let failed: Value = serde_json::from_slice(&records[1].data).unwrap();
+        assert_eq!(
+            failed,
+            json!({"message": "status=ok", "parseError": "invalid status \"ok\""})
+        );
+        assert_eq!(parsers.errors(), 2);
+
+        // Files no parser matches are forwarded as they are
+        let records = parsers.parse(
+            "/var/log/access.log",
+            vec![b"status=ok\n".to_vec()],
+            read_at,
+        );
+        assert_eq!(records[0].data, b"status=ok\n");
+        assert_eq!(parsers.errors(), 2);
+
+        assert!(Parsers::new(&[Parser {
+            path: String::from("/var/log/*.log"),
+            format: LogFormat::Regex,
+            pattern: None,
+        }])
+        .is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use crate::atomic;
+use crate::forwarder::checkpoint::Checkpoint;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+pub const REGISTRY_VERSION: u64 = 1;
+// Older versions only tailed the access log, its checkpoint lived next to it
+const LEGACY_CHECKPOINT_NAME: &str = ".checkpoint";
+const LEGACY_CHECKPOINT_VERSION: u64 = 1;
+
+// The checkpoints of every tailed file, keyed by path.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Registry {
+    pub version: u64,
+    pub files: BTreeMap<String, Checkpoint>,
+}
+
+// A missing registry holds the access log's checkpoint from before the registry,
+// if there is one. A corrupt registry is empty, its files start at the start position.
+pub fn load(path: &Path, access_log: &Path) -> io::Result<BTreeMap<String, Checkpoint>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            info!("no checkpoint registry at {}", path.display());
+            return import(access_log);
+        }
+        Err(err) => return Err(err),
+    };
+
+    match parse(&content) {
+        Ok(registry) => Ok(registry.files),
+        Err(reason) => {
+            warn!(
+                "ignoring corrupt checkpoint registry {}: {}",
+                path.display(),
+                reason
+            );
+            Ok(BTreeMap::new())
+        }
+    }
+}
+
+fn parse(content: &str) -> Result<Registry, String> {
+    let value: Value = serde_json::from_str(content).map_err(|err| err.to_string())?;
+    match value.get("version").and_then(Value::as_u64) {
+        Some(REGISTRY_VERSION) => serde_json::from_value(value).map_err(|err| err.to_string()),
+        Some(version) => Err(format!("unsupported version {}", version)),
+        None => Err(String::from("missing version")),
+    }
+}
+
+// The per-file checkpoint older versions saved next to the access log, a bare
+// offset or a versioned one naming its file. It's left in place, the registry
+// takes over once it's saved.
+fn import(access_log: &Path) -> io::Result<BTreeMap<String, Checkpoint>> {
+    let mut files = BTreeMap::new();
+    let Some(dir) = access_log
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+    else {
+        return Ok(files);
+    };
+    let path = dir.join(LEGACY_CHECKPOINT_NAME);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(files),
+        Err(err) => return Err(err),
+    };
+
+    let checkpoint = match content.trim().parse::<u64>() {
+        // Older checkpoints don't know their file, assume it's still the same
+        Ok(offset) => match File::open(access_log) {
+            Ok(file) => Checkpoint::new(&file, offset)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(files),
+            Err(err) => return Err(err),
+        },
+        Err(_) => match parse_legacy(&content) {
+            Ok(checkpoint) => checkpoint,
+            Err(reason) => {
+                warn!("ignoring corrupt checkpoint {}: {}", path.display(), reason);
+                return Ok(files);
+            }
+        },
+    };
+    info!(
+        "importing checkpoint {} for {}",
+        path.display(),
+        access_log.display()
+    );
+    files.insert(access_log.display().to_string(), checkpoint);
+    Ok(files)
+}
+
+fn parse_legacy(content: &str) -> Result<Checkpoint, String> {
+    let value: Value = serde_json::from_str(content).map_err(|err| err.to_string())?;
+    match value.get("version").and_then(Value::as_u64) {
+        Some(LEGACY_CHECKPOINT_VERSION) => {
+            serde_json::from_value(value).map_err(|err| err.to_string())
+        }
+        Some(version) => Err(format!("unsupported version {}", version)),
+        None => Err(String::from("missing version")),
+    }
+}
+
+// Replaces the registry atomically, see atomic::write.
+pub fn save(path: &Path, files: BTreeMap<String, Checkpoint>) -> io::Result<()> {
+    let registry = Registry {
+        version: REGISTRY_VERSION,
+        files,
+    };
+    let mut data = serde_json::to_vec(&registry)?;
+    data.push(b'\n');
+    atomic::write(path, &data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, save};
+    use crate::forwarder::checkpoint::Checkpoint;
+    use std::collections::BTreeMap;
+    use std::fs::{self, File};
+    use std::path::Path;
+
+    #[test]
+    fn registry_survives_interrupted_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("access.log");
+        fs::write(&log, "GET / 200\n").unwrap();
+        let registry = dir.path().join("registry.json");
+
+        let mut files = BTreeMap::new();
+        let checkpoint = Checkpoint::new(&File::open(&log).unwrap(), 10).unwrap();
+        files.insert(log.display().to_string(), checkpoint);
+        save(&registry, files).unwrap();
+
+        // A crash before the rename leaves a torn temp file behind
+        fs::write(dir.path().join("registry.json.tmp"), "{\"version\":1,\"fi").unwrap();
+        let files = load(&registry, &log).unwrap();
+        assert_eq!(files[&log.display().to_string()].offset, 10);
+
+        save(&registry, files).unwrap();
+        assert!(!dir.path().join("registry.json.tmp").exists());
+    }
+
+    #[test]
+    fn unusable_registry_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = dir.path().join("registry.json");
+        assert!(load(&registry, &dir.path().join("access.log"))
+            .unwrap()
+            .is_empty());
+
+        // Torn by a filesystem without atomic renames
+        fs::write(&registry, "{\"version\":1,\"fi\0\0").unwrap();
+        assert!(load(&registry, Path::new("access.log")).unwrap().is_empty());
+
+        fs::write(&registry, r#"{"version":2,"files":{}}"#).unwrap();
+        assert!(load(&registry, Path::new("access.log")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn imports_checkpoint_from_before_the_registry() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("access.log");
+        fs::write(&log, "GET / 200\nGET /a 404\n").unwrap();
+        let registry = dir.path().join("registry.json");
+        let key = log.display().to_string();
+
+        // A bare offset takes the access log's identity
+        fs::write(dir.path().join(".checkpoint"), "10\n").unwrap();
+        let files = load(&registry, &log).unwrap();
+        let current = Checkpoint::new(&File::open(&log).unwrap(), 10).unwrap();
+        assert_eq!(files[&key], current);
+
+        // A versioned one keeps its own, the file may have been rotated since
+        let mut saved = serde_json::to_value(&current).unwrap();
+        saved["version"] = 1.into();
+        saved["inode"] = 1.into();
+        fs::write(dir.path().join(".checkpoint"), saved.to_string()).unwrap();
+        assert_eq!(load(&registry, &log).unwrap()[&key].inode, 1);
+
+        fs::write(dir.path().join(".checkpoint"), r#"{"offset":10}"#).unwrap();
+        assert!(load(&registry, &log).unwrap().is_empty());
+
+        // An existing registry wins
+        fs::write(dir.path().join(".checkpoint"), "10\n").unwrap();
+        save(&registry, BTreeMap::new()).unwrap();
+        assert!(load(&registry, &log).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,67 @@
+use crate::configuration;
+use crate::forwarder::error::TransmitError;
+use crate::forwarder::loki::Loki;
+use crate::forwarder::network::Transmitter;
+use crate::forwarder::opensearch::OpenSearch;
+use crate::forwarder::parse::Record;
+use std::time::Duration;
+
+// Where the forwarder sends its batches.
+pub enum Sink {
+    // The records as they are, POSTed to the url
+    Http(Transmitter),
+    Loki(Loki),
+    OpenSearch(OpenSearch),
+}
+
+impl Sink {
+    // The configured sink, None when there's nowhere to forward to.
+    pub fn new(config: &configuration::Forwarder) -> Result<Option<Sink>, TransmitError> {
+        let timeout = Duration::from_secs(config.timeout_seconds);
+        match (&config.url, &config.loki, &config.open_search) {
+            (None, None, None) => Ok(None),
+            (Some(url), None, None) => Ok(Some(Sink::Http(Transmitter::new(url, timeout)?))),
+            (None, Some(loki), None) => Ok(Some(Sink::Loki(Loki::new(loki, timeout)?))),
+            (None, None, Some(open_search)) => Ok(Some(Sink::OpenSearch(OpenSearch::new(
+                open_search,
+                timeout,
+            )?))),
+            _ => Err(TransmitError::SeveralSinks),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        match self {
+            Sink::Http(transmitter) => transmitter.url(),
+            Sink::Loki(loki) => loki.url(),
+            Sink::OpenSearch(open_search) => open_search.url(),
+        }
+    }
+
+    // On failure records holds what's left to send, the others were accepted.
+    pub fn send(&self, records: &mut Vec<Record>) -> Result<(), TransmitError> {
+        match self {
+            Sink::Http(transmitter) => transmitter.transmit_data_chunk(concat(records)),
+            Sink::Loki(loki) => loki.push(records),
+            Sink::OpenSearch(open_search) => open_search.bulk(records),
+        }
+    }
+
+    // What send would send, readable for the dry run.
+    pub fn payload(&self, records: &[Record]) -> String {
+        match self {
+            Sink::Http(_) => String::from_utf8_lossy(&concat(records)).to_string(),
+            Sink::Loki(loki) => loki.json(records).to_string(),
+            Sink::OpenSearch(open_search) => {
+                String::from_utf8_lossy(&open_search.body(records)).to_string()
+            }
+        }
+    }
+}
+
+fn concat(records: &[Record]) -> Vec<u8> {
+    records
+        .iter()
+        .flat_map(|record| record.data.iter().copied())
+        .collect()
+}
@@ -1,141 +1,396 @@
+use crate::configuration;
+use crate::forwarder::checkpoint::{self, Checkpoint, Resume};
 use crate::forwarder::error::TailError;
-use log::debug;
-use std::fs::{self, File};
-use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
-use std::os::unix::fs::MetadataExt;
-use std::path::PathBuf;
+use crate::forwarder::framing::{Framer, Frames};
+use log::{debug, info};
+use std::collections::VecDeque;
+use std::fs::{self, File, Metadata};
+use std::io;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::Path;
 use std::result;
-use whoami;
 
 use crate::forwarder::file::FileExtension;
 
 pub struct Tail {
     path: String,
+    // Offset the server acknowledged everything up to
     checkpoint: u64,
+    // End of the last record handed out, ahead of checkpoint while a batch is in flight
+    position: u64,
+    // Holds the partial record read past position
+    framer: Framer,
     // We aim to reuse the file descriptor
     fd: File,
-    buffer_size: usize,
-    inode: u64,
+    // Files found at path since fd was rotated away, read in turn once fd is drained
+    successors: VecDeque<File>,
+    // Set once fd was truncated, until reading restarts at 0
+    truncated: bool,
+    // Reused by every read
+    buffer: Vec<u8>,
 }
 
 type Result<T> = result::Result<T, TailError>;
 
+const BUFFER_SIZE: usize = 65536;
+
 impl Tail {
-    pub fn new(path: String) -> Result<Tail> {
+    // Resumes from the file's saved checkpoint, without one reading starts at start.
+    pub fn new(
+        path: String,
+        framing: &configuration::Framing,
+        saved: Option<&Checkpoint>,
+        start: configuration::StartPosition,
+    ) -> Result<Tail> {
         let fd = fs::File::open(&path).map_err(|_| TailError::FileOpenError(path.clone()))?;
 
-        let md = fd.metadata()?;
-
         let mut tail = Tail {
             path: path.clone(),
             checkpoint: 0,
+            position: 0,
+            framer: Framer::new(framing)?,
             fd,
-            inode: md.ino(),
-            // hard code?
-            buffer_size: 65536,
+            successors: VecDeque::new(),
+            truncated: false,
+            buffer: vec![0; BUFFER_SIZE],
         };
 
-        tail.load_checkpoint()?;
+        tail.resume(saved, start)?;
 
         Ok(tail)
     }
 
-    pub fn next(&mut self) -> Result<Vec<u8>> {
-        self.handle_file_operations()?;
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn checkpoint(&self) -> u64 {
+        self.checkpoint
+    }
 
-        let mut buf_reader = BufReader::with_capacity(self.buffer_size, &self.fd);
-        let mut data = vec![0; self.buffer_size];
-        buf_reader
-            .seek(SeekFrom::Start(self.checkpoint))
-            .map_err(|err| TailError::StartSeekError(err))?;
+    pub fn position(&self) -> u64 {
+        self.position
+    }
 
-        let bytes_read = buf_reader
-            .read(&mut data)
-            .map_err(|err| TailError::BufferReadError(err))?;
+    // Moves the checkpoint once the data up to offset was delivered.
+    pub fn commit(&mut self, offset: u64) {
+        self.checkpoint = offset;
+    }
 
-        self.checkpoint += bytes_read as u64;
+    // Complete records read since the last call, each ending with the delimiter.
+    // position always ends up on a record boundary.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Vec<Vec<u8>>> {
+        if !self.handle_file_operations()? {
+            return Ok(vec![]);
+        }
 
-        let data = data.into_iter().take(bytes_read).collect();
-        Ok(data)
+        let offset = self.read_offset();
+        let bytes_read = self
+            .fd
+            .read_at(&mut self.buffer, offset)
+            .map_err(TailError::BufferReadError)?;
+
+        let mut frames = self.framer.push(&self.buffer[..bytes_read]);
+        if bytes_read == 0 && !self.successors.is_empty() {
+            frames = self.finish_draining();
+        } else if bytes_read == 0 && self.is_gone() {
+            // Deleted or renamed away for good, its last record won't be finished
+            frames = self.framer.finish();
+        }
+        self.position += frames.bytes;
+        Ok(frames.records)
     }
 
-    pub fn next_then<F: Fn(&[u8])>(&mut self, f: F) -> Result<()> {
-        let data = self.next()?;
-        f(&data);
-        Ok(())
+    // The rotated file won't grow anymore: its last record goes out even without
+    // a delimiter, then the next file is read once the checkpoint caught up, so no
+    // batch spans two files.
+    fn finish_draining(&mut self) -> Frames {
+        if self.framer.pending() > 0 {
+            return self.framer.finish();
+        }
+        if self.checkpoint < self.position {
+            return Frames::default();
+        }
+
+        if let Some(successor) = self.successors.pop_front() {
+            self.fd = successor;
+            self.checkpoint = 0;
+            self.position = 0;
+            info!("rotated file drained, continuing with {}", self.path);
+        }
+        Frames::default()
     }
 
-    fn handle_file_operations(&mut self) -> Result<()> {
-        // When a file was truncated, the content is deleted, but the inode number stays the same.
-        // Checkpoint is reset, read starts from the beginning of the file.
+    // The offset with the identity of the file it belongs to.
+    pub fn entry(&self) -> io::Result<Checkpoint> {
+        Checkpoint::new(&self.fd, self.checkpoint)
+    }
 
-        if self
-            .fd
-            .was_truncated(self.inode, self.checkpoint)
-            .map_err(|err| TailError::FileOperationError(err))?
+    // Whether md is one of the files this tail has open.
+    pub fn holds(&self, md: &Metadata) -> bool {
+        std::iter::once(&self.fd)
+            .chain(&self.successors)
+            .filter_map(|file| file.metadata().ok())
+            .any(|open| open.dev() == md.dev() && open.ino() == md.ino())
+    }
+
+    // The path is gone and everything read from its file was acknowledged, the
+    // file can be closed.
+    pub fn is_drained(&self) -> bool {
+        self.successors.is_empty()
+            && !self.truncated
+            && self.framer.pending() == 0
+            && self.checkpoint == self.position
+            && self.fd.metadata().is_ok_and(|md| md.len() <= self.position)
+            && self.is_gone()
+    }
+
+    fn is_gone(&self) -> bool {
+        matches!(fs::metadata(&self.path), Err(err) if err.kind() == io::ErrorKind::NotFound)
+    }
+
+    fn read_offset(&self) -> u64 {
+        self.position + self.framer.pending()
+    }
+
+    // False while a truncated file waits for the records read before its truncation.
+    fn handle_file_operations(&mut self) -> Result<bool> {
+        // When a file was truncated, e.g. by copytruncate, the content is deleted, but the
+        // inode number stays the same. Once the records read before were acknowledged,
+        // checkpoint is reset and read starts from the beginning of the file.
+        if !self.truncated
+            && self
+                .fd
+                .was_truncated(self.read_offset())
+                .map_err(TailError::FileOperationError)?
         {
-            self.checkpoint = 0;
+            self.truncated = true;
+            self.framer.reset();
             debug!("file was truncated")
         }
+        if self.truncated {
+            if self.checkpoint < self.position {
+                return Ok(false);
+            }
+            self.truncated = false;
+            self.checkpoint = 0;
+            self.position = 0;
+        }
 
         // When a file was rotated, it gets renamed & a new file is being created in its place,
-        // thus the inode number will differ from our stored inode.
-        // Checkpoint is reset, read starts from the beginning of the file.
-        if self
-            .fd
-            .was_rotated(self.inode)
-            .map_err(|err| TailError::FileOperationError(err))?
+        // thus the path names another inode than the last file opened from it. Each file is
+        // read to its end before the next one, see finish_draining.
+        let latest = self.successors.back().unwrap_or(&self.fd);
+        if latest
+            .was_rotated(Path::new(&self.path))
+            .map_err(TailError::FileOperationError)?
         {
-            self.inode = self.fd.metadata()?.ino();
-            self.checkpoint = 0;
-            debug!("file was rotated")
+            match File::open(&self.path) {
+                Ok(file) => {
+                    self.successors.push_back(file);
+                    debug!("file was rotated")
+                }
+                // Renamed away again meanwhile
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(TailError::FileOperationError(err)),
+            }
         }
 
+        Ok(true)
+    }
+
+    fn resume(
+        &mut self,
+        saved: Option<&Checkpoint>,
+        start: configuration::StartPosition,
+    ) -> Result<()> {
+        let resume = match saved {
+            Some(saved) => checkpoint::resume(saved, Path::new(&self.path), &self.fd)
+                .map_err(TailError::CheckpointError)?,
+            None => Resume::At(self.start_offset(start)?),
+        };
+
+        self.checkpoint = match resume {
+            Resume::At(offset) => offset,
+            Resume::Restart => {
+                info!(
+                    "{} was replaced since the checkpoint, starting over",
+                    self.path
+                );
+                0
+            }
+            Resume::Drain { file, path, offset } => {
+                info!("finishing rotated {} first", path.display());
+                let current = std::mem::replace(&mut self.fd, file);
+                self.successors.push_back(current);
+                offset
+            }
+        };
+        self.position = self.checkpoint;
+
+        debug!("{} resumed at {}", self.path, self.checkpoint);
         Ok(())
     }
 
-    // Tries to restore checkpoint from multiple locations.
-    fn load_checkpoint(&mut self) -> Result<()> {
-        // First look for .checkpoint in the same directory as the tailed file.
-        let checkpoint_name = ".checkpoint";
-        let path = PathBuf::from(self.path.clone());
+    // End starts after the last complete record, a partial one is forwarded once done.
+    fn start_offset(&self, start: configuration::StartPosition) -> Result<u64> {
+        if start == configuration::StartPosition::Beginning {
+            return Ok(0);
+        }
 
-        // Fallback to $HOME/.checkpoint
-        let default_checkpoint_path = default_path(&checkpoint_name);
-        let parent = path.parent().unwrap_or(default_checkpoint_path.as_path());
-        let mut parent = parent.to_path_buf();
-        parent.push(checkpoint_name);
+        let mut end = self.fd.metadata()?.len();
+        let mut data = vec![0; BUFFER_SIZE];
+        while end > 0 {
+            let len = end.min(BUFFER_SIZE as u64);
+            let data = &mut data[..len as usize];
+            self.fd
+                .read_exact_at(data, end - len)
+                .map_err(TailError::BufferReadError)?;
+            if let Some(index) = data.iter().rposition(|b| *b == self.framer.delimiter()) {
+                return Ok(end - len + index as u64 + 1);
+            }
+            end -= len;
+        }
+        Ok(0)
+    }
+}
 
-        let parent_str = parent.to_path_buf().into_os_string().into_string().unwrap();
+#[cfg(test)]
+mod tests {
+    use super::Tail;
+    use crate::configuration::StartPosition;
+    use crate::forwarder::checkpoint::Checkpoint;
+    use proptest::prelude::*;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::Path;
 
-        let fd = fs::File::open(parent).map_err(|_| TailError::FileOpenError(parent_str))?;
-        let mut reader = BufReader::new(fd);
-        let mut first_line = String::new();
-        let _ = reader.read_line(&mut first_line)?;
+    fn open(dir: &Path, saved: Option<&Checkpoint>, start: StartPosition) -> Tail {
+        let log = dir.join("access.log");
+        Tail::new(
+            log.to_string_lossy().to_string(),
+            &Default::default(),
+            saved,
+            start,
+        )
+        .unwrap()
+    }
 
-        self.checkpoint = first_line
-            .trim()
-            .parse::<u64>()
-            .map_err(|err| TailError::CastError(err))?;
+    #[test]
+    fn starts_at_start_position_without_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("access.log"),
+            "GET / 200\nGET /a 404\nGET /b",
+        )
+        .unwrap();
 
-        debug!("checkpoint restored: {}", self.checkpoint);
-        return Ok(());
+        assert_eq!(
+            open(dir.path(), None, StartPosition::Beginning).checkpoint(),
+            0
+        );
+        // After the last complete record
+        assert_eq!(open(dir.path(), None, StartPosition::End).checkpoint(), 21);
     }
 
-    // Save checkpoint on different SIGNALs
-    pub fn save_checkpoint(&self) -> Result<()> {
-        // TODO: implement
-        debug!("saving checkpoint");
-        Ok(())
+    #[test]
+    fn drains_file_rotated_while_stopped() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("access.log");
+        fs::write(&log, "GET / 200\n").unwrap();
+        let mut tail = open(dir.path(), None, StartPosition::Beginning);
+        tail.next().unwrap();
+        tail.commit(10);
+        let saved = tail.entry().unwrap();
+
+        // Written to and rotated while the helper was stopped
+        fs::write(&log, "GET / 200\nGET /a 404\nGET /b").unwrap();
+        fs::rename(&log, dir.path().join("access.log.1")).unwrap();
+        fs::write(&log, "GET /c 200\n").unwrap();
+
+        let mut tail = open(dir.path(), Some(&saved), StartPosition::Beginning);
+        assert_eq!(tail.checkpoint(), 10);
+        assert_eq!(tail.next().unwrap(), vec![b"GET /a 404\n".to_vec()]);
+        // The unterminated last record of the rotated file still goes out
+        assert_eq!(tail.next().unwrap(), vec![b"GET /b\n".to_vec()]);
+        assert_eq!(tail.position(), 27);
+
+        // Only switches once the rotated file's records were acknowledged
+        assert!(tail.next().unwrap().is_empty());
+        assert!(tail.next().unwrap().is_empty());
+        tail.commit(27);
+        assert!(tail.next().unwrap().is_empty());
+        assert_eq!(tail.next().unwrap(), vec![b"GET /c 200\n".to_vec()]);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Operation {
+        Write(Vec<String>),
+        // Renamed away, a new file takes its place
+        Rotate,
+        // Copied away, then emptied in place
+        CopyTruncate,
+    }
+
+    fn operation() -> impl Strategy<Value = Operation> {
+        prop_oneof![
+            4 => prop::collection::vec("[a-z ]{0,20}", 1..5).prop_map(Operation::Write),
+            1 => Just(Operation::Rotate),
+            1 => Just(Operation::CopyTruncate),
+        ]
     }
-}
 
-fn default_path(file_name: &str) -> PathBuf {
-    let mut default_path = match std::env::home_dir() {
-        Some(hd) => hd,
-        None => PathBuf::from(["/Users", &whoami::username()].join("/")),
-    };
-    default_path.push(file_name);
+    // Reads like the forwarder would, acknowledging every read right away.
+    fn read(tail: &mut Tail, records: &mut Vec<Vec<u8>>, times: usize) {
+        for _ in 0..times {
+            records.extend(tail.next().unwrap());
+            let position = tail.position();
+            tail.commit(position);
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        // Every line is forwarded exactly once and in order, as long as the tail
+        // looks at the path between two rotations and caught up before a copytruncate,
+        // which loses the lines written after its copy otherwise.
+        #[test]
+        fn follows_rotations_and_truncations(operations in prop::collection::vec(operation(), 1..30)) {
+            let dir = tempfile::tempdir().unwrap();
+            let log = dir.path().join("access.log");
+            fs::write(&log, "").unwrap();
+            let mut tail = open(dir.path(), None, StartPosition::Beginning);
+            let mut written = vec![];
+            let mut records = vec![];
 
-    default_path
+            for (n, operation) in operations.into_iter().enumerate() {
+                let archived = dir.path().join(format!("access.log.{}", n));
+                match operation {
+                    Operation::Write(lines) => {
+                        let mut file = OpenOptions::new().append(true).open(&log).unwrap();
+                        for line in lines {
+                            let line = format!("{}\n", line).into_bytes();
+                            file.write_all(&line).unwrap();
+                            written.push(line);
+                        }
+                    }
+                    Operation::Rotate => {
+                        fs::rename(&log, &archived).unwrap();
+                        fs::write(&log, "").unwrap();
+                    }
+                    Operation::CopyTruncate => {
+                        read(&mut tail, &mut records, 20);
+                        fs::copy(&log, &archived).unwrap();
+                        OpenOptions::new().write(true).truncate(true).open(&log).unwrap();
+                    }
+                }
+                read(&mut tail, &mut records, 1);
+            }
+            read(&mut tail, &mut records, 100);
+
+            prop_assert_eq!(records, written);
+        }
+    }
 }
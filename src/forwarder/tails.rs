@@ -0,0 +1,183 @@
+use crate::configuration::{self, StartPosition};
+use crate::forwarder::checkpoint::Checkpoint;
+use crate::forwarder::error::TailError;
+use crate::forwarder::registry;
+use crate::forwarder::tail::Tail;
+use glob::Pattern;
+use log::{debug, error, info};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Every file matching the forwarder's globs, each followed by name with its own
+// checkpoint in the registry.
+pub struct Tails {
+    patterns: Vec<String>,
+    framing: configuration::Framing,
+    registry_path: PathBuf,
+    tails: BTreeMap<String, Tail>,
+}
+
+impl Tails {
+    // Files present at startup resume from the registry, or start at the start position.
+    // Without a registry the access log resumes from its checkpoint of older versions.
+    pub fn open(
+        patterns: Vec<String>,
+        config: &configuration::Forwarder,
+        access_log: &Path,
+    ) -> Result<Tails, TailError> {
+        for pattern in &patterns {
+            Pattern::new(pattern).map_err(|err| TailError::PatternError(pattern.clone(), err))?;
+        }
+        let registry_path = PathBuf::from(&config.registry_path);
+        let mut saved =
+            registry::load(&registry_path, access_log).map_err(TailError::CheckpointError)?;
+
+        let mut tails = Tails {
+            patterns,
+            framing: config.framing.clone(),
+            registry_path,
+            tails: BTreeMap::new(),
+        };
+        tails.discover_with(&mut saved, config.start_position);
+        Ok(tails)
+    }
+
+    // Opens the files which started matching since, they're new and read from the start.
+    pub fn discover(&mut self) {
+        self.discover_with(&mut BTreeMap::new(), StartPosition::Beginning);
+    }
+
+    fn discover_with(&mut self, saved: &mut BTreeMap<String, Checkpoint>, start: StartPosition) {
+        for pattern in &self.patterns {
+            let Ok(paths) = glob::glob(pattern) else {
+                continue;
+            };
+            for path in paths.filter_map(Result::ok) {
+                let key = path.display().to_string();
+                let Ok(md) = fs::metadata(&path) else {
+                    continue;
+                };
+                // A rotated file still being drained under its old name
+                if !md.is_file()
+                    || self.tails.contains_key(&key)
+                    || self.tails.values().any(|tail| tail.holds(&md))
+                {
+                    continue;
+                }
+
+                match Tail::new(
+                    key.clone(),
+                    &self.framing,
+                    saved.remove(&key).as_ref(),
+                    start,
+                ) {
+                    Ok(tail) => {
+                        info!("tailing {}", key);
+                        self.tails.insert(key, tail);
+                    }
+                    Err(err) => error!("tailing {}: {}", key, err),
+                }
+            }
+        }
+    }
+
+    // Closes the files which are gone and were read and acknowledged to their end.
+    pub fn close_drained(&mut self) {
+        self.tails.retain(|path, tail| {
+            let drained = tail.is_drained();
+            if drained {
+                debug!("closing drained {}", path);
+            }
+            !drained
+        });
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Tail> {
+        self.tails.values_mut()
+    }
+
+    pub fn get_mut(&mut self, path: &str) -> Option<&mut Tail> {
+        self.tails.get_mut(path)
+    }
+
+    pub fn checkpoints(&self) -> Vec<(&str, u64)> {
+        self.tails
+            .iter()
+            .map(|(path, tail)| (path.as_str(), tail.checkpoint()))
+            .collect()
+    }
+
+    // Replaces the registry with the checkpoints of the open files.
+    pub fn save(&self) -> Result<(), TailError> {
+        let mut files = BTreeMap::new();
+        for (path, tail) in &self.tails {
+            files.insert(
+                path.clone(),
+                tail.entry().map_err(TailError::CheckpointError)?,
+            );
+        }
+        registry::save(&self.registry_path, files).map_err(TailError::CheckpointError)?;
+
+        debug!("checkpoints saved to {}", self.registry_path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tails;
+    use crate::configuration::{self, StartPosition};
+    use std::fs;
+    use std::path::Path;
+
+    fn config(dir: &Path, start_position: StartPosition) -> configuration::Forwarder {
+        configuration::Forwarder {
+            registry_path: dir.join("registry.json").display().to_string(),
+            start_position,
+            ..Default::default()
+        }
+    }
+
+    fn read(tails: &mut Tails) -> Vec<Vec<u8>> {
+        let mut records = vec![];
+        for tail in tails.iter_mut() {
+            records.extend(tail.next().unwrap());
+            let position = tail.position();
+            tail.commit(position);
+        }
+        records
+    }
+
+    #[test]
+    fn discovers_resumes_and_closes_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let pattern = dir.path().join("*.log").display().to_string();
+        let access = dir.path().join("access.log");
+        fs::write(&access, "GET / 200\n").unwrap();
+
+        // Present at startup without checkpoint, starts at the end
+        let config = config(dir.path(), StartPosition::End);
+        let mut tails = Tails::open(vec![pattern.clone()], &config, &access).unwrap();
+        assert_eq!(tails.checkpoints().len(), 1);
+
+        // Appearing later, read from the start
+        fs::write(dir.path().join("error.log"), "timeout\n").unwrap();
+        tails.discover();
+        assert_eq!(read(&mut tails), vec![b"timeout\n".to_vec()]);
+        tails.save().unwrap();
+
+        fs::write(dir.path().join("error.log"), "timeout\nrefused\n").unwrap();
+        let mut tails = Tails::open(vec![pattern], &config, &access).unwrap();
+        assert_eq!(read(&mut tails), vec![b"refused\n".to_vec()]);
+
+        // Drained before it's closed
+        fs::write(dir.path().join("error.log"), "timeout\nrefused\nreset\n").unwrap();
+        fs::remove_file(dir.path().join("error.log")).unwrap();
+        tails.close_drained();
+        assert_eq!(tails.checkpoints().len(), 2);
+        assert_eq!(read(&mut tails), vec![b"reset\n".to_vec()]);
+        tails.close_drained();
+        assert_eq!(tails.checkpoints(), vec![(access.to_str().unwrap(), 10)]);
+    }
+}
@@ -0,0 +1,127 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// Liveness of the long running parts. The watcher loop and the tailer thread beat
+// on every iteration, the systemd health loop reads the beats and the counters.
+pub struct Health {
+    started: Instant,
+    // Milliseconds since started, 0 until the first beat
+    watcher_beat: AtomicU64,
+    tailer_beat: AtomicU64,
+    queued: AtomicUsize,
+    quarantined: AtomicUsize,
+    parse_errors: AtomicU64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Component {
+    Watcher,
+    Tailer,
+}
+
+impl fmt::Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health {
+            started: Instant::now(),
+            watcher_beat: AtomicU64::new(0),
+            tailer_beat: AtomicU64::new(0),
+            queued: AtomicUsize::new(0),
+            quarantined: AtomicUsize::new(0),
+            parse_errors: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Health {
+    pub fn watcher_beat(&self) {
+        self.beat(&self.watcher_beat);
+    }
+
+    pub fn tailer_beat(&self) {
+        self.beat(&self.tailer_beat);
+    }
+
+    // Archives waiting for the debounce window or uploading right now.
+    pub fn set_queued(&self, queued: usize) {
+        self.queued.store(queued, Ordering::Relaxed);
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    // Corrupt archives sitting in a quarantine directory.
+    pub fn set_quarantined(&self, quarantined: usize) {
+        self.quarantined.store(quarantined, Ordering::Relaxed);
+    }
+
+    pub fn quarantined(&self) -> usize {
+        self.quarantined.load(Ordering::Relaxed)
+    }
+
+    // Forwarded lines which failed to parse.
+    pub fn set_parse_errors(&self, parse_errors: u64) {
+        self.parse_errors.store(parse_errors, Ordering::Relaxed);
+    }
+
+    pub fn parse_errors(&self) -> u64 {
+        self.parse_errors.load(Ordering::Relaxed)
+    }
+
+    // The first component which never beat or didn't beat during the last max_age.
+    pub fn stale(&self, now: Instant, max_age: Duration) -> Option<Component> {
+        let elapsed = now.duration_since(self.started).as_millis() as u64;
+        let is_stale = |beat: &AtomicU64| match beat.load(Ordering::Relaxed) {
+            0 => true,
+            at => elapsed.saturating_sub(at) > max_age.as_millis() as u64,
+        };
+
+        if is_stale(&self.watcher_beat) {
+            Some(Component::Watcher)
+        } else if is_stale(&self.tailer_beat) {
+            Some(Component::Tailer)
+        } else {
+            None
+        }
+    }
+
+    fn beat(&self, beat: &AtomicU64) {
+        let elapsed = self.started.elapsed().as_millis() as u64;
+        beat.store(elapsed.max(1), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Component, Health};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn stale_until_both_beat() {
+        let health = Health::default();
+        let max_age = Duration::from_secs(10);
+
+        assert_eq!(
+            health.stale(Instant::now(), max_age),
+            Some(Component::Watcher)
+        );
+        health.watcher_beat();
+        assert_eq!(
+            health.stale(Instant::now(), max_age),
+            Some(Component::Tailer)
+        );
+        health.tailer_beat();
+        assert_eq!(health.stale(Instant::now(), max_age), None);
+        assert_eq!(
+            health.stale(Instant::now() + Duration::from_secs(11), max_age),
+            Some(Component::Watcher)
+        );
+    }
+}
@@ -0,0 +1,13 @@
+pub mod atomic;
+pub mod configuration;
+pub mod dry_run;
+pub mod flags;
+pub mod forwarder;
+pub mod health;
+pub mod status;
+pub mod systemd;
+pub mod table;
+pub mod template;
+#[cfg(test)]
+mod testing;
+pub mod uploader;
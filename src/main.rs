@@ -1,41 +1,138 @@
-mod configuration;
-mod flags;
-mod forwarder;
-mod uploader;
-
-use crate::configuration::Configuration;
-use crate::flags::Flags;
-use forwarder::network::Transmitter;
-use forwarder::tail::Tail;
-use log::{debug, error};
+use log::{debug, error, info};
+use logga_helper::configuration::{self, Configuration};
+use logga_helper::dry_run::{self, DryRunEvent};
+use logga_helper::flags::{Command, Flags};
+use logga_helper::forwarder::events::{Wakeup, Wakeups};
+use logga_helper::forwarder::forward::Forwarder;
+use logga_helper::forwarder::parse::Parsers;
+use logga_helper::forwarder::sink::Sink;
+use logga_helper::forwarder::tails::Tails;
+use logga_helper::health::Health;
+use logga_helper::template::Template;
+use logga_helper::uploader::group::EndpointGroup;
+use logga_helper::uploader::history::History;
+use logga_helper::uploader::restore;
+use logga_helper::uploader::route::{Route, Router};
+use logga_helper::uploader::s3_client;
+use logga_helper::uploader::upload::Uploader;
+use logga_helper::uploader::watcher;
+use logga_helper::{status, systemd, table};
 use signal_hook::{consts::SIGINT, consts::SIGTERM, iterator::Signals};
+use std::path::Path;
 use std::process;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::thread::sleep;
-use std::time::Duration;
-use uploader::s3_client;
-use uploader::watcher;
+use std::time::{Duration, Instant};
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
-    let signals = Signals::new(&[SIGINT, SIGTERM]);
     let flags = Flags::build();
+
+    if let Some(Command::Status { json }) = flags.command {
+        if let Err(err) = status::run(&flags.history_path, json) {
+            error!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Install { systemd }) = flags.command {
+        if systemd {
+            let executable = std::env::current_exe()
+                .map(|path| path.display().to_string())
+                .unwrap_or_else(|_| String::from("/usr/local/bin/logga-helper"));
+            print!("{}", systemd::render_unit(&executable));
+        } else {
+            print!("{}", include_str!("../com.logga.helper.plist"));
+        }
+        return;
+    }
+
     let config = Configuration::build(&flags);
 
-    let client = match s3_client::create_s3_client(&config).await {
-        Ok(client) => client,
+    let key_template = match Template::parse(&config.s3.key_template) {
+        Ok(template) => template,
         Err(err) => {
-            error!("Couldn't create AWS client: {}", err);
-
+            error!("Invalid key template: {}", err);
             process::exit(1);
         }
     };
 
+    match &flags.command {
+        Some(Command::List { filter, json }) => {
+            let client = create_client(&config, false).await;
+            match restore::list_archives(&client, &config.s3.bucket, &key_template, filter).await {
+                Ok(archives) => print_archives(&archives, *json),
+                Err(err) => {
+                    error!("{}", err);
+                    process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::Restore {
+            filter,
+            output_dir,
+            concurrency,
+        }) => {
+            let client = create_client(&config, false).await;
+            let outcome =
+                match restore::list_archives(&client, &config.s3.bucket, &key_template, filter)
+                    .await
+                {
+                    Ok(archives) => {
+                        restore::restore(
+                            &client,
+                            &config.s3.bucket,
+                            archives,
+                            Path::new(output_dir),
+                            *concurrency,
+                        )
+                        .await
+                    }
+                    Err(err) => Err(err),
+                };
+            match outcome {
+                Ok(restored) => info!("restored {} archives", restored),
+                Err(err) => {
+                    error!("{}", err);
+                    process::exit(1);
+                }
+            }
+            return;
+        }
+        _ => {}
+    }
+
     // -------------------------------------------------
 
-    let tailer = match Tail::new(flags.access_log_path) {
+    // Registered only now, the subcommands above keep the default handlers
+    let mut signals = match Signals::new([SIGINT, SIGTERM]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            error!("registering signal handlers: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let patterns = if config.forwarder.paths.is_empty() {
+        vec![flags.access_log_path.clone()]
+    } else {
+        config.forwarder.paths.clone()
+    };
+    let parsers = match Parsers::new(&config.forwarder.parsers) {
+        Ok(parsers) => parsers,
+        Err(err) => {
+            error!("creating parsers: {}", err);
+            process::exit(1);
+        }
+    };
+    let wakeups = Wakeups::new(&patterns);
+    let tailer = match Tails::open(
+        patterns,
+        &config.forwarder,
+        Path::new(&flags.access_log_path),
+    ) {
         Ok(t) => t,
         Err(err) => {
             error!("creating tailer: {}", err);
@@ -44,45 +141,242 @@ async fn main() {
     };
     let tailer = Arc::new(Mutex::new(tailer));
     let signal_tailer = tailer.clone();
-    // Start tailing the access log file
-    let handle = thread::spawn(move || run_log_tailer(tailer.clone()));
+    // Start tailing the access logs
+    let dry_run = flags.dry_run;
+    let health = Arc::new(Health::default());
+    let tailer_health = health.clone();
+    let forwarder_config = config.forwarder.clone();
+    let handle = thread::spawn(move || {
+        run_log_tailer(
+            tailer.clone(),
+            wakeups,
+            parsers,
+            &forwarder_config,
+            dry_run,
+            tailer_health,
+        )
+    });
 
     // -------------------------------------------------
 
-    // Have tailer save current checkpoint on Signals & exit gracefully
+    // Have tailer save current checkpoints on Signals & exit gracefully
     thread::spawn(move || {
-        if let Ok(mut signals) = signals {
-            for _ in signals.forever() {
-                signal_tailer.lock().unwrap().save_checkpoint();
-                debug!("stopping gracefully...");
-                process::exit(0);
-            }
+        if let Some(signal) = signals.forever().next() {
+            debug!("received signal {}", signal);
+            save_checkpoints(&signal_tailer.lock().unwrap(), dry_run);
+            debug!("stopping gracefully...");
+            process::exit(0);
         }
     });
 
     // -------------------------------------------------
 
     // Start watching the specified directory for rotated archives
-    debug!("Watching directory: {}", flags.watch_dir);
-    if let Err(error) = watcher::watch(&flags.watch_dir, &client, &config.s3.bucket).await {
+    let history = match History::open(&flags.history_path) {
+        Ok(history) => history.with_retention(config.history.retention_days),
+        Err(err) => {
+            error!("{}", err);
+            process::exit(1);
+        }
+    };
+    let mut routes = vec![];
+    for watch in config.watches(&flags.watch_dir) {
+        // Bundles hold several archives, removing one can't delete its object
+        if config.mirror_deletions.enabled && watch.batch.is_some() {
+            error!(
+                "{}: batch can't be combined with mirrorDeletions",
+                watch.path
+            );
+            process::exit(1);
+        }
+        match Route::build(&watch, &config.s3) {
+            Ok(route) => routes.push(route),
+            Err(err) => {
+                error!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+    let router = Router::new(routes);
+    let watch_dirs: Vec<String> = router
+        .dirs()
+        .iter()
+        .map(|dir| dir.display().to_string())
+        .collect();
+    debug!("Watching directories: {}", watch_dirs.join(", "));
+
+    let group = match &config.destination_group {
+        Some(group) if group.endpoints.is_empty() => {
+            error!("destinationGroup has no endpoints");
+            process::exit(1);
+        }
+        Some(group) => match EndpointGroup::build(&config, group, flags.dry_run).await {
+            Ok(group) => group,
+            Err(err) => {
+                error!("Couldn't create AWS client: {}", err);
+                process::exit(1);
+            }
+        },
+        None => EndpointGroup::single(create_client(&config, flags.dry_run).await),
+    };
+    let uploader = Arc::new(Uploader::new(
+        group,
+        history,
+        config.integrity.clone(),
+        health.clone(),
+        flags.dry_run,
+    ));
+    // Readiness and watchdog pings when running under systemd
+    tokio::spawn(systemd::supervise(health.clone(), watch_dirs.join(", ")));
+    if let Err(error) = watcher::watch(
+        router,
+        &config.watcher,
+        &config.mirror_deletions,
+        uploader,
+        health,
+    )
+    .await
+    {
         error!("Problem watching directory: {error:?}");
     }
 
     handle.join().unwrap();
 }
 
-fn run_log_tailer(tailer: Arc<Mutex<Tail>>) {
-    let tr = Transmitter::new("TODO_URL");
+// The tailer beats at least this often, well within the health check's staleness limit
+const TAILER_BEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+// Exits when the client can't be created, e.g. without credentials.
+async fn create_client(config: &Configuration, dry_run: bool) -> aws_sdk_s3::Client {
+    match s3_client::create_s3_client(config, dry_run).await {
+        Ok(client) => client,
+        Err(err) => {
+            error!("Couldn't create AWS client: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+fn run_log_tailer(
+    tailer: Arc<Mutex<Tails>>,
+    wakeups: Wakeups,
+    parsers: Parsers,
+    config: &configuration::Forwarder,
+    dry_run: bool,
+    health: Arc<Health>,
+) {
+    // Without an endpoint there's nowhere to forward to, keep the tailer alive for health
+    let mut forwarder = match Sink::new(config) {
+        Ok(Some(sink)) => Some(Forwarder::new(
+            tailer.clone(),
+            sink,
+            config,
+            parsers,
+            dry_run,
+        )),
+        Ok(None) => {
+            info!(
+                "no forwarder url, loki or openSearch configured, not forwarding the access logs"
+            );
+            None
+        }
+        Err(err) => {
+            error!("creating forwarder: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let checkpoint_interval = Duration::from_secs(config.checkpoint_interval_seconds);
+    let discovery_interval = Duration::from_secs(config.discovery_interval_seconds);
+    let poll_interval = Duration::from_millis(config.poll_interval_ms).min(TAILER_BEAT_INTERVAL);
+    let mut saved = checkpoints(&tailer.lock().unwrap());
+    let mut last_save = Instant::now();
+    let mut last_discovery = Instant::now();
 
     loop {
-        match tailer.lock().unwrap().next_then(|data| {
-            // tr.transmit_data_chunk(data.into());
-            print!("{}", std::str::from_utf8(&data).unwrap());
-        }) {
-            Err(err) => error!("tail error: {}", err),
-            _ => {}
+        health.tailer_beat();
+        if last_discovery.elapsed() >= discovery_interval {
+            discover(&tailer);
+            last_discovery = Instant::now();
+        }
+        if last_save.elapsed() >= checkpoint_interval {
+            let tailer = tailer.lock().unwrap();
+            let current = checkpoints(&tailer);
+            if current != saved {
+                save_checkpoints(&tailer, dry_run);
+                saved = current;
+            }
+            last_save = Instant::now();
+        }
+
+        let read = forwarder
+            .as_mut()
+            .is_some_and(|forwarder| forwarder.poll(Instant::now()));
+        if let Some(forwarder) = &forwarder {
+            health.set_parse_errors(forwarder.parse_errors());
+        }
+        // Keep reading while there's data, the timers above still get their turn
+        if read {
+            continue;
         }
 
-        sleep(Duration::from_millis(100));
+        // Sleep until the files change, the next timer or the safety poll
+        let now = Instant::now();
+        let mut wake_at = (now + poll_interval)
+            .min(last_discovery + discovery_interval)
+            .min(last_save + checkpoint_interval);
+        if let Some(deadline) = forwarder.as_ref().and_then(Forwarder::next_deadline) {
+            wake_at = wake_at.min(deadline);
+        }
+        if let Wakeup::Changed = wakeups.wait(wake_at.saturating_duration_since(now)) {
+            discover(&tailer);
+        }
     }
 }
+
+fn discover(tailer: &Mutex<Tails>) {
+    let mut tailer = tailer.lock().unwrap();
+    tailer.close_drained();
+    tailer.discover();
+}
+
+fn checkpoints(tailer: &Tails) -> Vec<(String, u64)> {
+    tailer
+        .checkpoints()
+        .into_iter()
+        .map(|(path, offset)| (path.to_string(), offset))
+        .collect()
+}
+
+fn save_checkpoints(tailer: &Tails, dry_run: bool) {
+    if dry_run {
+        for (path, offset) in tailer.checkpoints() {
+            dry_run::report(&DryRunEvent::Checkpoint { path, offset });
+        }
+    } else if let Err(err) = tailer.save() {
+        error!("saving checkpoints: {}", err);
+    }
+}
+
+fn print_archives(archives: &[restore::Archive], json: bool) {
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(archives).unwrap_or_default()
+        );
+        return;
+    }
+
+    let rows = archives
+        .iter()
+        .map(|archive| {
+            [
+                archive.key.clone(),
+                archive.size.to_string(),
+                archive.date.map(|d| d.to_string()).unwrap_or_default(),
+                archive.host.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+    print!("{}", table::render(["KEY", "SIZE", "DATE", "HOST"], rows));
+}
@@ -0,0 +1,85 @@
+use crate::table;
+use crate::uploader::history::{History, HistoryError, UploadRecord};
+use time::format_description::well_known::Rfc3339;
+
+const HEADERS: [&str; 6] = ["STATUS", "ATTEMPTS", "SIZE", "UPDATED", "KEY", "LAST ERROR"];
+
+pub fn run(history_path: &str, json: bool) -> Result<(), HistoryError> {
+    let history = History::open(history_path)?;
+    let mut records: Vec<&UploadRecord> = history.records().collect();
+    // Pending first, then failed and quarantined, completed last; most recent first
+    // within a status
+    records.sort_by(|a, b| {
+        a.status
+            .cmp(&b.status)
+            .then_with(|| b.updated_at.cmp(&a.updated_at))
+    });
+
+    if json {
+        let output = serde_json::to_string_pretty(&records).map_err(HistoryError::Parse)?;
+        println!("{}", output);
+    } else {
+        print!("{}", render_table(&records));
+    }
+
+    Ok(())
+}
+
+fn render_table(records: &[&UploadRecord]) -> String {
+    let rows: Vec<[String; 6]> = records
+        .iter()
+        .map(|record| {
+            let status = match record.corruption {
+                Some(_) => format!("{} (corrupt)", record.status),
+                None => record.status.to_string(),
+            };
+            [
+                status,
+                record.attempts.to_string(),
+                record.size.to_string(),
+                record.updated_at.format(&Rfc3339).unwrap_or_default(),
+                record.key.clone(),
+                record
+                    .last_error
+                    .clone()
+                    .or_else(|| record.corruption.clone())
+                    .unwrap_or_default(),
+            ]
+        })
+        .collect();
+
+    table::render(HEADERS, rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render_table;
+    use crate::uploader::history::{UploadRecord, UploadStatus};
+    use time::macros::datetime;
+
+    #[test]
+    fn table_layout() {
+        let record = UploadRecord {
+            path: String::from("/logs/a.zip"),
+            bucket: String::from("bucket"),
+            key: String::from("a.zip"),
+            member: None,
+            corruption: None,
+            size: 1024,
+            checksum: String::from("abc"),
+            attempts: 2,
+            status: UploadStatus::Failed,
+            last_error: Some(String::from("timeout")),
+            first_attempt_at: datetime!(2024-04-01 10:00 UTC),
+            updated_at: datetime!(2024-04-01 10:05 UTC),
+            endpoints: Default::default(),
+            deletion_due_at: None,
+        };
+
+        assert_eq!(
+            render_table(&[&record]),
+            "STATUS  ATTEMPTS  SIZE  UPDATED               KEY    LAST ERROR\n\
+             failed  2         1024  2024-04-01T10:05:00Z  a.zip  timeout\n"
+        )
+    }
+}
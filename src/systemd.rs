@@ -0,0 +1,193 @@
+use crate::health::Health;
+use log::{debug, warn};
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Beats older than this mean the watcher or the tailer hangs
+const STALE_AFTER: Duration = Duration::from_secs(10);
+const LOG_PATH: &str = "/var/log/logga/helper.log";
+// How often the status is refreshed when systemd doesn't ask for watchdog pings
+const STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+// Tells systemd the helper is ready once the watcher and the tailer beat, then keeps
+// pinging the watchdog for as long as both are alive. A no-op outside of systemd.
+pub async fn supervise(health: Arc<Health>, watch_dir: String) {
+    let socket = match env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => socket,
+        None => return,
+    };
+    let watchdog = watchdog_interval(
+        env::var("WATCHDOG_USEC").ok().as_deref(),
+        env::var("WATCHDOG_PID").ok().as_deref(),
+        process::id(),
+    );
+
+    while health.stale(Instant::now(), STALE_AFTER).is_some() {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    send(
+        &socket,
+        &format!("READY=1\n{}", status(&health, &watch_dir)),
+    );
+    debug!("notified systemd, watchdog: {:?}", watchdog);
+
+    // Ping twice per watchdog interval, as sd_watchdog_enabled(3) recommends
+    let period = watchdog.map_or(STATUS_INTERVAL, |interval| interval / 2);
+    loop {
+        tokio::time::sleep(period).await;
+
+        match health.stale(Instant::now(), STALE_AFTER) {
+            None if watchdog.is_some() => send(
+                &socket,
+                &format!("WATCHDOG=1\n{}", status(&health, &watch_dir)),
+            ),
+            None => send(&socket, &status(&health, &watch_dir)),
+            // Skipping the ping lets systemd restart the helper
+            Some(component) => {
+                warn!("{} stopped responding", component);
+                send(&socket, &format!("STATUS={} stopped responding", component))
+            }
+        }
+    }
+}
+
+fn status(health: &Health, watch_dir: &str) -> String {
+    let mut status = format!(
+        "STATUS=watching {}, {} archives queued",
+        watch_dir,
+        health.queued()
+    );
+    if health.quarantined() > 0 {
+        status.push_str(&format!(", {} quarantined", health.quarantined()));
+    }
+    if health.parse_errors() > 0 {
+        status.push_str(&format!(
+            ", {} lines failed to parse",
+            health.parse_errors()
+        ));
+    }
+    status
+}
+
+fn send(socket: &OsStr, state: &str) {
+    if let Err(err) = notify(socket, state) {
+        warn!("notifying systemd: {}", err);
+    }
+}
+
+// sd_notify(3): one datagram of newline separated assignments. Sockets starting with
+// @ live in the abstract namespace.
+fn notify(socket: &OsStr, state: &str) -> io::Result<()> {
+    let datagram = UnixDatagram::unbound()?;
+    match socket.as_bytes() {
+        [b'@', name @ ..] => send_abstract(&datagram, name, state),
+        _ => datagram.send_to(state.as_bytes(), socket).map(|_| ()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(datagram: &UnixDatagram, name: &[u8], state: &str) -> io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    let addr = SocketAddr::from_abstract_name(name)?;
+    datagram.send_to_addr(state.as_bytes(), &addr).map(|_| ())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_abstract(_: &UnixDatagram, _: &[u8], _: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "abstract sockets are Linux only",
+    ))
+}
+
+// sd_watchdog_enabled(3): WATCHDOG_USEC is set when WatchdogSec= is configured,
+// WATCHDOG_PID, when present, must be this process.
+fn watchdog_interval(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok() != Some(own_pid) {
+            return None;
+        }
+    }
+    match usec?.parse::<u64>() {
+        Ok(0) | Err(_) => None,
+        Ok(usec) => Some(Duration::from_micros(usec)),
+    }
+}
+
+// A unit equivalent to com.logga.helper.plist: restarted when it exits or stops
+// pinging the watchdog, started at boot, logging next to the archives.
+pub fn render_unit(executable: &str) -> String {
+    format!(
+        "[Unit]
+Description=logga helper, backs up rotated logs to S3
+After=network-online.target
+Wants=network-online.target
+
+[Service]
+Type=notify
+NotifyAccess=main
+WatchdogSec=30
+Environment=RUST_LOG=info
+ExecStart={executable}
+Restart=always
+RestartSec=5
+StandardOutput=append:{LOG_PATH}
+StandardError=append:{LOG_PATH}
+
+[Install]
+WantedBy=multi-user.target
+"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{notify, render_unit, watchdog_interval};
+    use std::os::unix::net::UnixDatagram;
+    use std::time::Duration;
+
+    #[test]
+    fn notify_sends_datagram() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let listener = UnixDatagram::bind(&path).unwrap();
+
+        notify(path.as_os_str(), "READY=1\nSTATUS=watching").unwrap();
+
+        let mut buf = [0; 64];
+        let len = listener.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1\nSTATUS=watching");
+    }
+
+    #[test]
+    fn watchdog_interval_from_env() {
+        assert_eq!(
+            watchdog_interval(Some("30000000"), None, 42),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            watchdog_interval(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(watchdog_interval(Some("30000000"), Some("7"), 42), None);
+        assert_eq!(watchdog_interval(Some("0"), None, 42), None);
+        assert_eq!(watchdog_interval(None, None, 42), None);
+    }
+
+    #[test]
+    fn unit_runs_executable() {
+        let unit = render_unit("/usr/local/bin/logga-helper");
+
+        assert!(unit.contains("\nType=notify\n"));
+        assert!(unit.contains("\nExecStart=/usr/local/bin/logga-helper\n"));
+        assert!(unit.contains("\nStandardError=append:/var/log/logga/helper.log\n"));
+    }
+}
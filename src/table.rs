@@ -0,0 +1,22 @@
+// Renders left-aligned columns separated by two spaces, headers first.
+pub fn render<const N: usize>(headers: [&str; N], rows: Vec<[String; N]>) -> String {
+    let mut widths = headers.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut table = String::new();
+    for row in std::iter::once(headers.map(String::from)).chain(rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        table.push_str(line.join("  ").trim_end());
+        table.push('\n');
+    }
+
+    table
+}
@@ -0,0 +1,280 @@
+use std::fmt;
+use time::OffsetDateTime;
+
+// Placeholders are enclosed in braces. `{host}` and `{filename}` are replaced by the
+// uploading machine's hostname and the archive's file name, anything else is a date
+// pattern built from yyyy, MM, dd, HH, mm and ss, e.g. `{host}/{yyyy/MM/dd}/{filename}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Host,
+    Filename,
+    Date(Vec<DatePart>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DatePart {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    Separator(char),
+}
+
+const DATE_TOKENS: [(&str, DatePart); 6] = [
+    ("yyyy", DatePart::Year),
+    ("MM", DatePart::Month),
+    ("dd", DatePart::Day),
+    ("HH", DatePart::Hour),
+    ("mm", DatePart::Minute),
+    ("ss", DatePart::Second),
+];
+
+#[derive(Debug, PartialEq)]
+pub enum TemplateError {
+    Unclosed(String),
+    UnknownPlaceholder(String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TemplateError::Unclosed(template) => write!(f, "unclosed placeholder in {}", template),
+            TemplateError::UnknownPlaceholder(placeholder) => {
+                write!(f, "unknown placeholder {{{}}}", placeholder)
+            }
+        }
+    }
+}
+
+pub struct TemplateContext<'a> {
+    pub host: &'a str,
+    pub filename: &'a str,
+    pub time: OffsetDateTime,
+}
+
+// Values recovered from a rendered string, see Template::parse_rendered.
+#[derive(Debug, Default, PartialEq)]
+pub struct Captures {
+    pub host: Option<String>,
+    pub filename: Option<String>,
+    pub date: Option<time::Date>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template, TemplateError> {
+        let mut segments = vec![];
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| TemplateError::Unclosed(template.to_string()))?;
+            segments.push(parse_placeholder(&rest[start + 1..start + end])?);
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+
+        Ok(Template { segments })
+    }
+
+    pub fn render(&self, context: &TemplateContext) -> String {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => rendered.push_str(literal),
+                Segment::Host => rendered.push_str(context.host),
+                Segment::Filename => rendered.push_str(context.filename),
+                Segment::Date(parts) => {
+                    for part in parts {
+                        rendered.push_str(&render_date_part(part, &context.time));
+                    }
+                }
+            }
+        }
+        rendered
+    }
+
+    // Everything before the first placeholder, usable as a listing prefix.
+    pub fn static_prefix(&self) -> &str {
+        match self.segments.first() {
+            Some(Segment::Literal(literal)) => literal,
+            _ => "",
+        }
+    }
+
+    // Reverses render: matches a rendered string and extracts the placeholders' values.
+    pub fn parse_rendered(&self, rendered: &str) -> Option<Captures> {
+        let mut pattern = String::from("^");
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => pattern.push_str(&regex::escape(literal)),
+                Segment::Host => pattern.push_str("(?P<host>[^/]+)"),
+                Segment::Filename => pattern.push_str("(?P<filename>[^/]+)"),
+                Segment::Date(parts) => {
+                    for part in parts {
+                        pattern.push_str(&date_part_pattern(part));
+                    }
+                }
+            }
+        }
+        pattern.push('$');
+
+        let regex = regex::Regex::new(&pattern).ok()?;
+        let captures = regex.captures(rendered)?;
+        let number = |name: &str| {
+            captures
+                .name(name)
+                .and_then(|value| value.as_str().parse::<u16>().ok())
+        };
+
+        let date = match (number("year"), number("month"), number("day")) {
+            (Some(year), Some(month), Some(day)) => {
+                time::Month::try_from(month as u8).ok().and_then(|month| {
+                    time::Date::from_calendar_date(year as i32, month, day as u8).ok()
+                })
+            }
+            _ => None,
+        };
+
+        Some(Captures {
+            host: captures
+                .name("host")
+                .map(|value| value.as_str().to_string()),
+            filename: captures
+                .name("filename")
+                .map(|value| value.as_str().to_string()),
+            date,
+        })
+    }
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Segment, TemplateError> {
+    match placeholder {
+        "host" => return Ok(Segment::Host),
+        "filename" => return Ok(Segment::Filename),
+        _ => {}
+    }
+
+    let mut parts = vec![];
+    let mut rest = placeholder;
+    'outer: while let Some(c) = rest.chars().next() {
+        for (token, part) in DATE_TOKENS {
+            if rest.starts_with(token) {
+                parts.push(part);
+                rest = &rest[token.len()..];
+                continue 'outer;
+            }
+        }
+        if c.is_alphanumeric() {
+            return Err(TemplateError::UnknownPlaceholder(placeholder.to_string()));
+        }
+        parts.push(DatePart::Separator(c));
+        rest = &rest[c.len_utf8()..];
+    }
+
+    if parts.is_empty() {
+        return Err(TemplateError::UnknownPlaceholder(placeholder.to_string()));
+    }
+    Ok(Segment::Date(parts))
+}
+
+fn render_date_part(part: &DatePart, time: &OffsetDateTime) -> String {
+    match part {
+        DatePart::Year => format!("{:04}", time.year()),
+        DatePart::Month => format!("{:02}", time.month() as u8),
+        DatePart::Day => format!("{:02}", time.day()),
+        DatePart::Hour => format!("{:02}", time.hour()),
+        DatePart::Minute => format!("{:02}", time.minute()),
+        DatePart::Second => format!("{:02}", time.second()),
+        DatePart::Separator(c) => c.to_string(),
+    }
+}
+
+fn date_part_pattern(part: &DatePart) -> String {
+    match part {
+        DatePart::Year => String::from("(?P<year>\\d{4})"),
+        DatePart::Month => String::from("(?P<month>\\d{2})"),
+        DatePart::Day => String::from("(?P<day>\\d{2})"),
+        DatePart::Hour | DatePart::Minute | DatePart::Second => String::from("\\d{2}"),
+        DatePart::Separator(c) => regex::escape(&c.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Captures, Template, TemplateContext, TemplateError};
+    use time::macros::{date, datetime};
+
+    #[test]
+    fn render_key() {
+        let template = Template::parse("logs/{host}/{yyyy/MM/dd}/{filename}").unwrap();
+        let rendered = template.render(&TemplateContext {
+            host: "mac-01",
+            filename: "access-1.zip",
+            time: datetime!(2024-04-01 10:05:09 UTC),
+        });
+
+        assert_eq!(rendered, "logs/mac-01/2024/04/01/access-1.zip")
+    }
+
+    #[test]
+    fn render_index_pattern() {
+        let template = Template::parse("logga-{yyyy.MM.dd}").unwrap();
+        let rendered = template.render(&TemplateContext {
+            host: "",
+            filename: "",
+            time: datetime!(2024-12-31 23:59 UTC),
+        });
+
+        assert_eq!(rendered, "logga-2024.12.31")
+    }
+
+    #[test]
+    fn unknown_placeholder() {
+        assert_eq!(
+            Template::parse("{hostname}/{filename}"),
+            Err(TemplateError::UnknownPlaceholder(String::from("hostname")))
+        )
+    }
+
+    #[test]
+    fn unclosed_placeholder() {
+        assert!(matches!(
+            Template::parse("{host/{filename}"),
+            Err(TemplateError::UnknownPlaceholder(_))
+        ));
+        assert!(matches!(
+            Template::parse("{filename"),
+            Err(TemplateError::Unclosed(_))
+        ))
+    }
+
+    #[test]
+    fn parse_rendered_key() {
+        let template = Template::parse("logs/{host}/{yyyy-MM-dd}/{filename}").unwrap();
+
+        assert_eq!(template.static_prefix(), "logs/");
+        assert_eq!(
+            template.parse_rendered("logs/mac-01/2024-04-01/access-1.zip"),
+            Some(Captures {
+                host: Some(String::from("mac-01")),
+                filename: Some(String::from("access-1.zip")),
+                date: Some(date!(2024 - 04 - 01)),
+            })
+        );
+        assert_eq!(template.parse_rendered("other/access-1.zip"), None)
+    }
+}
@@ -0,0 +1,82 @@
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use aws_sdk_s3::Client;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+pub struct Request {
+    pub path: String,
+    // Lowercase names
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+// Stands in for the endpoints batches and archives are sent to: answers each
+// request with respond's status and body, and hands out the requests.
+pub fn stand_in<F>(mut respond: F) -> (String, Receiver<Request>)
+where
+    F: FnMut(&Request) -> (u16, String) + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                    break;
+                }
+                let path = line.split(' ').nth(1).unwrap_or_default().to_string();
+
+                let mut headers = BTreeMap::new();
+                line.clear();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                    if let Some((name, value)) = line.split_once(':') {
+                        headers.insert(name.to_lowercase(), value.trim().to_string());
+                    }
+                    line.clear();
+                }
+                let length = headers
+                    .get("content-length")
+                    .map_or(0, |length| length.parse().unwrap());
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let request = Request {
+                    path,
+                    headers,
+                    body,
+                };
+                let (status, body) = respond(&request);
+                let _ = tx.send(request);
+                let response = format!(
+                    "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        }
+    });
+    (url, rx)
+}
+
+// A path-style S3 client talking to a stand-in.
+pub fn s3_client(url: &str) -> Client {
+    Client::from_conf(
+        aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("eu-west-1"))
+            .credentials_provider(Credentials::new("id", "secret", None, None, "test"))
+            .endpoint_url(url)
+            .force_path_style(true)
+            .build(),
+    )
+}
@@ -0,0 +1,92 @@
+use crate::configuration::DeletionMode;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use time::OffsetDateTime;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry<'a> {
+    #[serde(with = "time::serde::rfc3339")]
+    pub time: OffsetDateTime,
+    pub mode: DeletionMode,
+    pub path: &'a str,
+    // Destination group endpoint, absent without a group
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<&'a str>,
+    pub bucket: &'a str,
+    pub key: &'a str,
+    // Why the object was kept, no request was sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+    pub error: Option<String>,
+}
+
+// Append-only JSON lines record of every mirrored deletion, successful, failed
+// or skipped.
+pub struct AuditLog {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new<P: Into<PathBuf>>(path: P) -> AuditLog {
+        AuditLog {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn append(&self, entry: &AuditEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let _guard = self.lock.lock().unwrap();
+        let mut fd = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        fd.write_all(&line)?;
+        fd.sync_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuditEntry, AuditLog};
+    use crate::configuration::DeletionMode;
+    use std::fs;
+    use time::macros::datetime;
+
+    #[test]
+    fn appends_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let audit = AuditLog::new(&path);
+
+        for key in ["a.zip", "b.zip"] {
+            audit
+                .append(&AuditEntry {
+                    time: datetime!(2024-04-01 10:00 UTC),
+                    mode: DeletionMode::Delete,
+                    path: "/logs/a.zip",
+                    endpoint: None,
+                    bucket: "bucket",
+                    key,
+                    skipped: None,
+                    error: None,
+                })
+                .unwrap();
+        }
+
+        let content = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[1],
+            r#"{"time":"2024-04-01T10:00:00Z","mode":"delete","path":"/logs/a.zip","bucket":"bucket","key":"b.zip","error":null}"#
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use crate::uploader::route::Route;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Archives waiting to be uploaded together.
+pub struct Bundle {
+    pub route: Arc<Route>,
+    pub members: Vec<PathBuf>,
+}
+
+struct Pending {
+    route: Arc<Route>,
+    members: Vec<(PathBuf, u64)>,
+    opened: Instant,
+}
+
+impl Pending {
+    fn bytes(&self) -> u64 {
+        self.members.iter().map(|(_, size)| size).sum()
+    }
+
+    fn deadline(&self) -> Instant {
+        let max_age = self.route.batch.as_ref().map_or(0, |b| b.max_age_seconds);
+        self.opened + Duration::from_secs(max_age)
+    }
+
+    fn into_bundle(self) -> Bundle {
+        Bundle {
+            route: self.route,
+            members: self.members.into_iter().map(|(path, _)| path).collect(),
+        }
+    }
+}
+
+// Collects the ready archives of watches with a batch section, one bundle per
+// watch. A bundle is handed out once its archives reach the watch's max_bytes
+// or the first archive waited max_age_seconds.
+#[derive(Default)]
+pub struct Batcher {
+    pending: Vec<Pending>,
+}
+
+impl Batcher {
+    // Returns the archive's bundle when it became full.
+    pub fn push(
+        &mut self,
+        path: PathBuf,
+        size: u64,
+        route: Arc<Route>,
+        now: Instant,
+    ) -> Option<Bundle> {
+        let index = match self
+            .pending
+            .iter()
+            .position(|p| Arc::ptr_eq(&p.route, &route))
+        {
+            Some(index) => index,
+            None => {
+                self.pending.push(Pending {
+                    route,
+                    members: vec![],
+                    opened: now,
+                });
+                self.pending.len() - 1
+            }
+        };
+
+        let pending = &mut self.pending[index];
+        // The archive changed again before its bundle left
+        pending.members.retain(|(member, _)| *member != path);
+        pending.members.push((path, size));
+
+        let max_bytes = pending.route.batch.as_ref().map_or(0, |b| b.max_bytes);
+        if pending.bytes() >= max_bytes {
+            return Some(self.pending.remove(index).into_bundle());
+        }
+        None
+    }
+
+    // Drops an archive removed before its bundle was uploaded.
+    pub fn remove(&mut self, path: &Path) {
+        for pending in &mut self.pending {
+            pending.members.retain(|(member, _)| member != path);
+        }
+        self.pending.retain(|pending| !pending.members.is_empty());
+    }
+
+    // Bundles whose first archive waited long enough, removed from the batcher.
+    pub fn ready(&mut self, now: Instant) -> Vec<Bundle> {
+        let (ready, waiting) = self
+            .pending
+            .drain(..)
+            .partition(|pending| pending.deadline() <= now);
+        self.pending = waiting;
+        ready.into_iter().map(Pending::into_bundle).collect()
+    }
+
+    // Number of archives waiting for their bundle.
+    pub fn pending(&self) -> usize {
+        self.pending
+            .iter()
+            .map(|pending| pending.members.len())
+            .sum()
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending.iter().map(Pending::deadline).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Batcher;
+    use crate::configuration::{Batch, Watch, S3};
+    use crate::uploader::route::Route;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    fn route() -> Arc<Route> {
+        let watch = Watch {
+            path: String::from("/logs"),
+            include: vec![String::from("*.zip")],
+            exclude: vec![],
+            destination: Default::default(),
+            after_upload: Default::default(),
+            batch: Some(Batch {
+                max_bytes: 100,
+                max_age_seconds: 60,
+            }),
+        };
+        let s3 = S3 {
+            key_template: String::from("{filename}"),
+            ..Default::default()
+        };
+        Arc::new(Route::build(&watch, &s3).unwrap())
+    }
+
+    fn path(name: &str) -> PathBuf {
+        PathBuf::from("/logs").join(name)
+    }
+
+    #[test]
+    fn full_bundle_is_handed_out() {
+        let start = Instant::now();
+        let route = route();
+        let mut batcher = Batcher::default();
+
+        assert!(batcher
+            .push(path("a.zip"), 40, route.clone(), start)
+            .is_none());
+        // Counted once, with its latest size
+        assert!(batcher
+            .push(path("a.zip"), 50, route.clone(), start)
+            .is_none());
+        let bundle = batcher
+            .push(path("b.zip"), 50, route.clone(), start)
+            .unwrap();
+
+        assert_eq!(bundle.members, vec![path("a.zip"), path("b.zip")]);
+        assert_eq!(batcher.pending(), 0);
+        assert_eq!(batcher.next_deadline(), None);
+    }
+
+    #[test]
+    fn old_bundle_is_handed_out() {
+        let start = Instant::now();
+        let route = route();
+        let mut batcher = Batcher::default();
+
+        batcher.push(path("a.zip"), 10, route.clone(), start);
+        batcher.push(
+            path("b.zip"),
+            10,
+            route.clone(),
+            start + Duration::from_secs(30),
+        );
+        batcher.push(
+            path("c.zip"),
+            10,
+            route.clone(),
+            start + Duration::from_secs(40),
+        );
+        batcher.remove(&path("b.zip"));
+
+        assert_eq!(
+            batcher.next_deadline(),
+            Some(start + Duration::from_secs(60))
+        );
+        assert!(batcher.ready(start + Duration::from_secs(59)).is_empty());
+        let ready = batcher.ready(start + Duration::from_secs(60));
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].members, vec![path("a.zip"), path("c.zip")]);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::uploader::{history, restore};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+
+// Appended to a bundle's key for the key of its index object
+pub const INDEX_SUFFIX: &str = ".index.json";
+// Object metadata marking bundles, restore unpacks those
+pub const BUNDLE_METADATA: &str = "bundle";
+
+// Uploaded next to every bundle, lists the archives inside.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleIndex {
+    pub host: String,
+    pub members: Vec<Member>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Member {
+    // File name of the archive, its path inside the tar
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub rotated_at: OffsetDateTime,
+}
+
+#[derive(Debug)]
+pub enum BundleError {
+    Io(io::Error),
+    MissingMember(String),
+    // The index names a member outside the directory
+    UnsafeName(String),
+    ChecksumMismatch {
+        name: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl fmt::Display for BundleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BundleError::Io(err) => write!(f, "{}", err),
+            BundleError::MissingMember(name) => write!(f, "{} is missing from the bundle", name),
+            BundleError::UnsafeName(name) => write!(f, "{} leaves the bundle's directory", name),
+            BundleError::ChecksumMismatch {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "checksum mismatch for {}: expected {}, got {}",
+                name, expected, actual
+            ),
+        }
+    }
+}
+
+impl From<io::Error> for BundleError {
+    fn from(err: io::Error) -> Self {
+        BundleError::Io(err)
+    }
+}
+
+// Writes the archives into a tar at path, each under its member name.
+pub fn write_tar(path: &Path, members: &[(PathBuf, Member)]) -> io::Result<()> {
+    let mut builder = tar::Builder::new(BufWriter::new(File::create(path)?));
+    for (file_path, member) in members {
+        builder.append_path_with_name(file_path, &member.name)?;
+    }
+    let mut writer = builder.into_inner()?;
+    writer.flush()
+}
+
+// Unpacks a bundle into dir and checks every member the index lists, returning
+// their number. Members failing the check are removed again.
+pub fn unpack(tar_path: &Path, dir: &Path, index: &BundleIndex) -> Result<usize, BundleError> {
+    fs::create_dir_all(dir)?;
+    let mut archive = tar::Archive::new(BufReader::new(File::open(tar_path)?));
+    for entry in archive.entries()? {
+        // unpack_in refuses paths escaping dir
+        entry?.unpack_in(dir)?;
+    }
+
+    for member in &index.members {
+        if !restore::is_relative(&member.name) {
+            return Err(BundleError::UnsafeName(member.name.clone()));
+        }
+        let path = dir.join(&member.name);
+        if !path.exists() {
+            return Err(BundleError::MissingMember(member.name.clone()));
+        }
+        let actual = history::sha256_file(&path)?;
+        if actual != member.sha256 {
+            let _ = fs::remove_file(&path);
+            return Err(BundleError::ChecksumMismatch {
+                name: member.name.clone(),
+                expected: member.sha256.clone(),
+                actual,
+            });
+        }
+    }
+
+    Ok(index.members.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unpack, write_tar, BundleError, BundleIndex, Member};
+    use crate::uploader::history::sha256_file;
+    use std::fs;
+    use time::OffsetDateTime;
+
+    #[test]
+    fn bundle_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut members = vec![];
+        for name in ["a.zip", "b.zip"] {
+            let path = dir.path().join(name);
+            fs::write(&path, name).unwrap();
+            let member = Member {
+                name: name.to_string(),
+                size: 5,
+                sha256: sha256_file(&path).unwrap(),
+                rotated_at: OffsetDateTime::UNIX_EPOCH,
+            };
+            members.push((path, member));
+        }
+        let tar = dir.path().join("bundle.tar");
+        write_tar(&tar, &members).unwrap();
+
+        let mut index = BundleIndex {
+            host: String::from("mac-01"),
+            members: members.into_iter().map(|(_, member)| member).collect(),
+        };
+        let restored = dir.path().join("restored");
+        assert_eq!(unpack(&tar, &restored, &index).unwrap(), 2);
+        assert_eq!(fs::read_to_string(restored.join("b.zip")).unwrap(), "b.zip");
+
+        index.members[1].sha256 = String::from("abc");
+        let tampered = dir.path().join("tampered");
+        assert!(matches!(
+            unpack(&tar, &tampered, &index),
+            Err(BundleError::ChecksumMismatch { name, .. }) if name == "b.zip"
+        ));
+        assert!(!tampered.join("b.zip").exists());
+
+        index.members[1].name = String::from("../a.zip");
+        assert!(matches!(
+            unpack(&tar, &dir.path().join("escaped"), &index),
+            Err(BundleError::UnsafeName(name)) if name == "../a.zip"
+        ));
+    }
+}
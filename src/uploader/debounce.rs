@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+// What happened to a path in the watched directory, see watcher::classify.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileEvent {
+    // Created, written to, renamed or moved into the directory
+    Changed(PathBuf),
+    // Removed, renamed away or moved out of the directory
+    Removed(PathBuf),
+}
+
+// Collapses the bursts of events notify emits for a single file into one
+// "archive ready" notification per path. A path is ready once no new event
+// arrived for it during the debounce window, so files still being written
+// aren't picked up half-way.
+pub struct Debouncer {
+    window: Duration,
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl Debouncer {
+    pub fn new(window: Duration) -> Debouncer {
+        Debouncer {
+            window,
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, evt: &FileEvent, now: Instant) {
+        match evt {
+            FileEvent::Changed(path) => {
+                self.pending.insert(path.clone(), now);
+            }
+            FileEvent::Removed(path) => {
+                self.pending.remove(path);
+            }
+        }
+    }
+
+    // Paths which were quiet for the whole window, removed from the pending set.
+    pub fn ready(&mut self, now: Instant) -> Vec<PathBuf> {
+        let window = self.window;
+        let mut ready: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, last_event)| now.duration_since(**last_event) >= window)
+            .map(|(path, _)| path.clone())
+            .collect();
+        ready.sort();
+
+        for path in &ready {
+            self.pending.remove(path);
+        }
+        ready
+    }
+
+    // Number of paths waiting for their window to pass.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    // When the next path becomes ready, None if nothing is pending.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending
+            .values()
+            .min()
+            .map(|last_event| *last_event + self.window)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Debouncer, FileEvent};
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+
+    fn changed(path: &str) -> FileEvent {
+        FileEvent::Changed(PathBuf::from(path))
+    }
+
+    #[test]
+    fn coalesces_bursts_into_one_event() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(Duration::from_millis(500));
+
+        debouncer.push(&changed("/logs/a.zip"), start);
+        debouncer.push(&changed("/logs/a.zip"), start + Duration::from_millis(300));
+
+        assert!(debouncer
+            .ready(start + Duration::from_millis(600))
+            .is_empty());
+        assert_eq!(
+            debouncer.next_deadline(),
+            Some(start + Duration::from_millis(800))
+        );
+        assert_eq!(
+            debouncer.ready(start + Duration::from_millis(800)),
+            vec![PathBuf::from("/logs/a.zip")]
+        );
+        assert!(debouncer.ready(start + Duration::from_secs(10)).is_empty());
+        assert_eq!(debouncer.next_deadline(), None);
+    }
+
+    #[test]
+    fn rename_moves_pending_state() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(Duration::from_millis(500));
+
+        debouncer.push(&changed("/logs/a.zip.tmp"), start);
+        debouncer.push(
+            &FileEvent::Removed(PathBuf::from("/logs/a.zip.tmp")),
+            start + Duration::from_millis(100),
+        );
+        debouncer.push(&changed("/logs/a.zip"), start + Duration::from_millis(100));
+
+        assert_eq!(
+            debouncer.ready(start + Duration::from_millis(600)),
+            vec![PathBuf::from("/logs/a.zip")]
+        );
+    }
+
+    #[test]
+    fn removal_drops_pending_path() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(Duration::from_millis(500));
+
+        debouncer.push(&changed("/logs/a.zip"), start);
+        debouncer.push(
+            &FileEvent::Removed(PathBuf::from("/logs/a.zip")),
+            start + Duration::from_millis(100),
+        );
+
+        assert!(debouncer.ready(start + Duration::from_secs(1)).is_empty());
+        assert_eq!(debouncer.next_deadline(), None);
+    }
+
+    #[test]
+    fn paths_are_independent() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(Duration::from_millis(500));
+
+        debouncer.push(&changed("/logs/a.zip"), start);
+        debouncer.push(&changed("/logs/b.zip"), start + Duration::from_millis(400));
+
+        assert_eq!(
+            debouncer.ready(start + Duration::from_millis(500)),
+            vec![PathBuf::from("/logs/a.zip")]
+        );
+        assert_eq!(
+            debouncer.ready(start + Duration::from_millis(900)),
+            vec![PathBuf::from("/logs/b.zip")]
+        );
+    }
+}
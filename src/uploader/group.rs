@@ -0,0 +1,271 @@
+use crate::configuration::{Configuration, DestinationGroup, GroupMode};
+use crate::uploader::history::{UploadRecord, UploadStatus};
+use crate::uploader::s3_client::{self, ClientError};
+use aws_sdk_s3::Client;
+use std::time::Duration;
+
+pub struct Endpoint {
+    pub name: String,
+    pub client: Client,
+    // Replaces the route's bucket on this endpoint
+    pub bucket: Option<String>,
+}
+
+impl Endpoint {
+    pub fn bucket<'a>(&'a self, route_bucket: &'a str) -> &'a str {
+        self.bucket.as_deref().unwrap_or(route_bucket)
+    }
+}
+
+// The endpoints archives are uploaded to. Without a destination group, the s3
+// section's endpoint alone.
+pub struct EndpointGroup {
+    endpoints: Vec<Endpoint>,
+    mode: GroupMode,
+    // Confirmations an upload needs to count as backed up
+    required: usize,
+    catch_up_interval: Option<Duration>,
+}
+
+impl EndpointGroup {
+    pub fn single(client: Client) -> EndpointGroup {
+        let endpoint = Endpoint {
+            name: String::from("s3"),
+            client,
+            bucket: None,
+        };
+        EndpointGroup::new(vec![endpoint], GroupMode::Failover, None, 0)
+    }
+
+    pub async fn build<'a>(
+        config: &'a Configuration,
+        group: &DestinationGroup,
+        dry_run: bool,
+    ) -> Result<EndpointGroup, ClientError<'a>> {
+        let mut endpoints = vec![];
+        for endpoint in &group.endpoints {
+            endpoints.push(Endpoint {
+                name: endpoint.name.clone(),
+                client: s3_client::create_endpoint_client(config, endpoint, dry_run).await?,
+                bucket: endpoint.bucket.clone(),
+            });
+        }
+
+        Ok(EndpointGroup::new(
+            endpoints,
+            group.mode,
+            group.required_confirmations,
+            group.catch_up_interval_seconds,
+        ))
+    }
+
+    pub fn new(
+        endpoints: Vec<Endpoint>,
+        mode: GroupMode,
+        required_confirmations: Option<usize>,
+        catch_up_interval_seconds: u64,
+    ) -> EndpointGroup {
+        let required = match mode {
+            GroupMode::Failover => 1,
+            GroupMode::Replicate => required_confirmations
+                .unwrap_or(endpoints.len())
+                .min(endpoints.len())
+                .max(1),
+        };
+        // Failover never leaves an endpoint behind
+        let catch_up_interval = match (mode, catch_up_interval_seconds) {
+            (GroupMode::Replicate, seconds) if seconds > 0 => Some(Duration::from_secs(seconds)),
+            _ => None,
+        };
+
+        EndpointGroup {
+            endpoints,
+            mode,
+            required,
+            catch_up_interval,
+        }
+    }
+
+    pub fn endpoints(&self) -> &[Endpoint] {
+        &self.endpoints
+    }
+
+    pub fn mode(&self) -> GroupMode {
+        self.mode
+    }
+
+    // Events and audit entries only name the endpoint when there's a choice.
+    pub fn is_group(&self) -> bool {
+        self.endpoints.len() > 1
+    }
+
+    pub fn catch_up_interval(&self) -> Option<Duration> {
+        self.catch_up_interval
+    }
+
+    // The endpoints an upload still has to reach, in order. Failover mode tries
+    // them until one accepts the archive.
+    pub fn pending(&self, record: Option<&UploadRecord>) -> Vec<&Endpoint> {
+        let confirmed = |endpoint: &Endpoint| record.is_some_and(|r| r.confirmed(&endpoint.name));
+        if self.mode == GroupMode::Failover && self.endpoints.iter().any(confirmed) {
+            return vec![];
+        }
+        self.endpoints
+            .iter()
+            .filter(|endpoint| !confirmed(endpoint))
+            .collect()
+    }
+
+    // Enough endpoints confirmed the upload for the archive to count as backed up.
+    pub fn satisfied(&self, record: &UploadRecord) -> bool {
+        self.confirmations(record) >= self.required
+    }
+
+    // Every endpoint which should hold the archive does, nothing is left to catch up.
+    // Records from before the group was configured, or naming only endpoints since
+    // renamed or removed, count as complete, the group doesn't backfill them.
+    pub fn complete(&self, record: &UploadRecord) -> bool {
+        if !self.knows(record) {
+            return record.status == UploadStatus::Completed;
+        }
+        match self.mode {
+            GroupMode::Failover => self.confirmations(record) > 0,
+            GroupMode::Replicate => self.confirmations(record) == self.endpoints.len(),
+        }
+    }
+
+    // The endpoints holding the archive. Records none of the group's endpoints
+    // know only reached the first one, the s3 section's endpoint.
+    pub fn holders(&self, record: &UploadRecord) -> Vec<&Endpoint> {
+        if !self.knows(record) {
+            return self.endpoints.iter().take(1).collect();
+        }
+        self.endpoints
+            .iter()
+            .filter(|endpoint| record.confirmed(&endpoint.name))
+            .collect()
+    }
+
+    fn knows(&self, record: &UploadRecord) -> bool {
+        self.endpoints
+            .iter()
+            .any(|endpoint| record.endpoints.contains_key(&endpoint.name))
+    }
+
+    fn confirmations(&self, record: &UploadRecord) -> usize {
+        self.endpoints
+            .iter()
+            .filter(|endpoint| record.confirmed(&endpoint.name))
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Endpoint, EndpointGroup};
+    use crate::configuration::GroupMode;
+    use crate::uploader::history::{EndpointRecord, UploadRecord, UploadStatus};
+    use aws_sdk_s3::config::{BehaviorVersion, Region};
+    use aws_sdk_s3::Client;
+    use std::collections::BTreeMap;
+    use time::OffsetDateTime;
+
+    fn group(mode: GroupMode, required: Option<usize>) -> EndpointGroup {
+        let client = Client::from_conf(
+            aws_sdk_s3::Config::builder()
+                .behavior_version(BehaviorVersion::latest())
+                .region(Region::new("eu-west-1"))
+                .build(),
+        );
+        let endpoints = ["primary", "backup", "offsite"]
+            .into_iter()
+            .map(|name| Endpoint {
+                name: name.to_string(),
+                client: client.clone(),
+                bucket: None,
+            })
+            .collect();
+        EndpointGroup::new(endpoints, mode, required, 300)
+    }
+
+    fn record(confirmed: &[&str], failed: &[&str]) -> UploadRecord {
+        let mut endpoints = BTreeMap::new();
+        for (names, status) in [
+            (confirmed, UploadStatus::Completed),
+            (failed, UploadStatus::Failed),
+        ] {
+            for name in names {
+                let last_error = None;
+                endpoints.insert(name.to_string(), EndpointRecord { status, last_error });
+            }
+        }
+        UploadRecord {
+            path: String::from("/logs/a.zip"),
+            bucket: String::from("logs"),
+            key: String::from("a.zip"),
+            member: None,
+            corruption: None,
+            size: 3,
+            checksum: String::from("abc"),
+            attempts: 1,
+            status: UploadStatus::Completed,
+            last_error: None,
+            first_attempt_at: OffsetDateTime::UNIX_EPOCH,
+            updated_at: OffsetDateTime::UNIX_EPOCH,
+            endpoints,
+            deletion_due_at: None,
+        }
+    }
+
+    fn names(endpoints: Vec<&Endpoint>) -> Vec<&str> {
+        endpoints.iter().map(|e| e.name.as_str()).collect()
+    }
+
+    #[test]
+    fn failover_stops_at_first_confirmation() {
+        let group = group(GroupMode::Failover, None);
+
+        assert_eq!(names(group.pending(None)), ["primary", "backup", "offsite"]);
+        let record = record(&["backup"], &["primary"]);
+        assert!(group.pending(Some(&record)).is_empty());
+        assert!(group.satisfied(&record));
+        assert!(group.complete(&record));
+        assert_eq!(group.catch_up_interval(), None);
+    }
+
+    #[test]
+    fn replicate_needs_confirmations_and_catches_up() {
+        let group = group(GroupMode::Replicate, Some(2));
+
+        let one = record(&["primary"], &["backup", "offsite"]);
+        assert!(!group.satisfied(&one));
+        assert_eq!(names(group.pending(Some(&one))), ["backup", "offsite"]);
+
+        let two = record(&["primary", "offsite"], &["backup"]);
+        assert!(group.satisfied(&two));
+        assert!(!group.complete(&two));
+        assert_eq!(names(group.pending(Some(&two))), ["backup"]);
+
+        let all = record(&["primary", "backup", "offsite"], &[]);
+        assert!(group.complete(&all));
+        assert!(group.pending(Some(&all)).is_empty());
+    }
+
+    #[test]
+    fn replicate_requires_all_by_default() {
+        let group = group(GroupMode::Replicate, None);
+        assert!(!group.satisfied(&record(&["primary", "backup"], &["offsite"])));
+
+        // Records from before the group existed, or naming a removed endpoint
+        assert!(group.complete(&record(&[], &[])));
+        assert!(group.complete(&record(&["s3"], &[])));
+        assert_eq!(names(group.holders(&record(&["s3"], &[]))), ["primary"]);
+        assert_eq!(
+            names(group.holders(&record(&["backup"], &["primary"]))),
+            ["backup"]
+        );
+
+        let clamped = EndpointGroup::new(vec![], GroupMode::Replicate, Some(5), 0);
+        assert_eq!(clamped.required, 1);
+    }
+}
@@ -0,0 +1,548 @@
+use crate::atomic;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{fmt, result};
+use time::OffsetDateTime;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum UploadStatus {
+    Pending,
+    Failed,
+    // A corrupt archive, moved to the quarantine directory instead of uploaded
+    Quarantined,
+    Completed,
+    // The local archive was removed and the deletion mirrored to the bucket
+    Deleted,
+}
+
+impl fmt::Display for UploadStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadRecord {
+    pub path: String,
+    pub bucket: String,
+    pub key: String,
+    // Name inside the bundle at key, for archives uploaded as part of one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub member: Option<String>,
+    // Why the archive failed its integrity check, when it was uploaded anyway
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub corruption: Option<String>,
+    pub size: u64,
+    pub checksum: String,
+    pub attempts: u32,
+    pub status: UploadStatus,
+    pub last_error: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub first_attempt_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub updated_at: OffsetDateTime,
+    // Outcome per endpoint of a destination group, empty for older records
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub endpoints: BTreeMap<String, EndpointRecord>,
+    // The archive was removed, its deletion is mirrored at this time
+    #[serde(
+        default,
+        with = "time::serde::rfc3339::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub deletion_due_at: Option<OffsetDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointRecord {
+    pub status: UploadStatus,
+    pub last_error: Option<String>,
+}
+
+impl UploadRecord {
+    // Endpoints holding the archive as it was last uploaded.
+    pub fn confirmed(&self, endpoint: &str) -> bool {
+        self.endpoints
+            .get(endpoint)
+            .is_some_and(|record| record.status == UploadStatus::Completed)
+    }
+}
+
+#[derive(Debug)]
+pub enum HistoryError {
+    Read(io::Error),
+    Parse(serde_json::Error),
+    Write(io::Error),
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HistoryError::Read(err) => write!(f, "reading upload history: {}", err),
+            HistoryError::Parse(err) => write!(f, "parsing upload history: {}", err),
+            HistoryError::Write(err) => write!(f, "writing upload history: {}", err),
+        }
+    }
+}
+
+type Result<T> = result::Result<T, HistoryError>;
+
+// Pruning checks the remaining old records' archives, it doesn't run on every save
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Upload attempts keyed by archive path, persisted as a single JSON document.
+// Changes are made in memory and written out by save or a snapshot, through a
+// temporary file renamed over the store, so a crash never leaves a
+// half-written history behind.
+pub struct History {
+    path: PathBuf,
+    records: BTreeMap<String, UploadRecord>,
+    // Finished records are forgotten this long after their archive is gone
+    retention: Option<Duration>,
+    pruned_at: Option<Instant>,
+    // Counts the snapshots, a later one holds every change of an earlier one
+    snapshots: u64,
+    // Changed since the last snapshot
+    changed: bool,
+}
+
+// The serialized history at one point in time, written on its own so the
+// history isn't locked meanwhile.
+pub struct Snapshot {
+    path: PathBuf,
+    number: u64,
+    data: Vec<u8>,
+}
+
+impl Snapshot {
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+
+    pub fn write(&self) -> Result<()> {
+        atomic::write(&self.path, &self.data).map_err(HistoryError::Write)
+    }
+}
+
+impl History {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<History> {
+        let path = path.as_ref().to_path_buf();
+        let records = match File::open(&path) {
+            Ok(fd) => serde_json::from_reader(BufReader::new(fd)).map_err(HistoryError::Parse)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(HistoryError::Read(err)),
+        };
+
+        Ok(History {
+            path,
+            records,
+            retention: None,
+            pruned_at: None,
+            snapshots: 0,
+            changed: false,
+        })
+    }
+
+    // Forgets finished uploads of archives gone for retention_days, 0 keeps them.
+    pub fn with_retention(mut self, retention_days: u64) -> History {
+        self.retention =
+            (retention_days > 0).then(|| Duration::from_secs(retention_days * 24 * 60 * 60));
+        self
+    }
+
+    pub fn get(&self, path: &str) -> Option<&UploadRecord> {
+        self.records.get(path)
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &UploadRecord> {
+        self.records.values()
+    }
+
+    // Marks an upload as started, counting the attempt.
+    pub fn start_attempt(
+        &mut self,
+        path: &str,
+        bucket: &str,
+        key: &str,
+        member: Option<&str>,
+        size: u64,
+        checksum: &str,
+    ) {
+        let now = OffsetDateTime::now_utc();
+        let record = self.entry(path, bucket, key, size, checksum, now);
+
+        // The archive may have been replaced since the last attempt, its new
+        // content has to reach every endpoint again
+        if record.checksum != checksum {
+            record.endpoints.clear();
+        }
+        record.bucket = bucket.to_string();
+        record.key = key.to_string();
+        record.member = member.map(str::to_string);
+        record.corruption = None;
+        record.size = size;
+        record.checksum = checksum.to_string();
+        record.attempts += 1;
+        record.status = UploadStatus::Pending;
+        record.updated_at = now;
+        record.deletion_due_at = None;
+        self.changed = true;
+    }
+
+    // Marks an archive waiting in a bundle, a restart before the bundle is
+    // uploaded finds it unfinished and queues it again.
+    pub fn queue(&mut self, path: &str, bucket: &str, size: u64) {
+        let now = OffsetDateTime::now_utc();
+        let record = self.entry(path, bucket, "", size, "", now);
+        record.status = UploadStatus::Pending;
+        record.updated_at = now;
+        self.changed = true;
+    }
+
+    fn entry(
+        &mut self,
+        path: &str,
+        bucket: &str,
+        key: &str,
+        size: u64,
+        checksum: &str,
+        now: OffsetDateTime,
+    ) -> &mut UploadRecord {
+        self.records
+            .entry(path.to_string())
+            .or_insert_with(|| UploadRecord {
+                path: path.to_string(),
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+                member: None,
+                corruption: None,
+                size,
+                checksum: checksum.to_string(),
+                attempts: 0,
+                status: UploadStatus::Pending,
+                last_error: None,
+                first_attempt_at: now,
+                updated_at: now,
+                endpoints: BTreeMap::new(),
+                deletion_due_at: None,
+            })
+    }
+
+    pub fn complete(&mut self, path: &str) {
+        self.finish(path, UploadStatus::Completed, None)
+    }
+
+    pub fn fail(&mut self, path: &str, error: String) {
+        self.finish(path, UploadStatus::Failed, Some(error))
+    }
+
+    // Records the outcome of the current attempt on one endpoint.
+    pub fn endpoint_finished(
+        &mut self,
+        path: &str,
+        endpoint: &str,
+        outcome: result::Result<(), String>,
+    ) {
+        if let Some(record) = self.records.get_mut(path) {
+            let (status, last_error) = match outcome {
+                Ok(()) => (UploadStatus::Completed, None),
+                Err(err) => (UploadStatus::Failed, Some(err)),
+            };
+            record
+                .endpoints
+                .insert(endpoint.to_string(), EndpointRecord { status, last_error });
+            self.changed = true;
+        }
+    }
+
+    pub fn quarantine(&mut self, path: &str, reason: String) {
+        self.finish(path, UploadStatus::Quarantined, Some(reason))
+    }
+
+    // Flags the current attempt as the upload of a corrupt archive.
+    pub fn mark_corrupt(&mut self, path: &str, reason: String) {
+        if let Some(record) = self.records.get_mut(path) {
+            record.corruption = Some(reason);
+            self.changed = true;
+        }
+    }
+
+    // Remembers the mirrored deletion of a completed upload, so it survives restarts.
+    pub fn schedule_deletion(&mut self, path: &str, due_at: OffsetDateTime) {
+        if let Some(record) = self.records.get_mut(path) {
+            record.deletion_due_at = Some(due_at);
+            self.changed = true;
+        }
+    }
+
+    pub fn cancel_deletion(&mut self, path: &str) {
+        if let Some(record) = self.records.get_mut(path) {
+            self.changed |= record.deletion_due_at.take().is_some();
+        }
+    }
+
+    // Deletions still to be mirrored, with their due time.
+    pub fn pending_deletions(&self) -> Vec<(String, OffsetDateTime)> {
+        self.records
+            .values()
+            .filter(|record| record.status == UploadStatus::Completed)
+            .filter_map(|record| Some((record.path.clone(), record.deletion_due_at?)))
+            .collect()
+    }
+
+    pub fn mark_deleted(&mut self, path: &str) {
+        if let Some(record) = self.records.get_mut(path) {
+            record.deletion_due_at = None;
+        }
+        self.finish(path, UploadStatus::Deleted, None)
+    }
+
+    // Removes pending and failed records, finished uploads are kept.
+    pub fn remove_unfinished(&mut self, path: &str) {
+        if self.records.get(path).is_some_and(|record| {
+            record.status == UploadStatus::Pending || record.status == UploadStatus::Failed
+        }) {
+            self.records.remove(path);
+            self.changed = true;
+        }
+    }
+
+    fn finish(&mut self, path: &str, status: UploadStatus, error: Option<String>) {
+        if let Some(record) = self.records.get_mut(path) {
+            record.status = status;
+            record.last_error = error;
+            record.updated_at = OffsetDateTime::now_utc();
+            self.changed = true;
+        }
+    }
+
+    // Drops finished records older than the retention whose archive is gone. A
+    // completed record of an archive still on disk keeps it from being uploaded
+    // again, a scheduled deletion still needs its record.
+    fn prune(&mut self) {
+        let Some(retention) = self.retention else {
+            return;
+        };
+        if self
+            .pruned_at
+            .is_some_and(|pruned_at| pruned_at.elapsed() < PRUNE_INTERVAL)
+        {
+            return;
+        }
+        self.pruned_at = Some(Instant::now());
+
+        let cutoff = OffsetDateTime::now_utc() - retention;
+        let count = self.records.len();
+        self.records.retain(|path, record| {
+            let finished = matches!(
+                record.status,
+                UploadStatus::Completed | UploadStatus::Quarantined | UploadStatus::Deleted
+            );
+            !finished
+                || record.updated_at >= cutoff
+                || record.deletion_due_at.is_some()
+                || Path::new(path).exists()
+        });
+        self.changed |= self.records.len() != count;
+    }
+
+    // Whether a snapshot would hold anything new.
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    // Serializes the history for writing, see Snapshot.
+    pub fn snapshot(&mut self) -> Result<Snapshot> {
+        self.prune();
+        self.snapshots += 1;
+        self.changed = false;
+        Ok(Snapshot {
+            path: self.path.clone(),
+            number: self.snapshots,
+            data: serde_json::to_vec(&self.records).map_err(HistoryError::Parse)?,
+        })
+    }
+
+    pub fn save(&mut self) -> Result<()> {
+        self.snapshot()?.write()
+    }
+}
+
+// Hex encoded SHA-256 of the file, read in chunks to keep memory bounded.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 65536];
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sha256_file, History, UploadStatus};
+    use std::fs;
+    use time::macros::datetime;
+
+    #[test]
+    fn missing_store_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::open(dir.path().join("history.json")).unwrap();
+        assert_eq!(history.records().count(), 0)
+    }
+
+    #[test]
+    fn attempts_are_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("history.json");
+
+        let mut history = History::open(&store).unwrap();
+        history.start_attempt("/logs/a.zip", "bucket", "a.zip", None, 3, "abc");
+        history.fail("/logs/a.zip", String::from("timeout"));
+        history.start_attempt("/logs/a.zip", "bucket", "a.zip", None, 3, "abc");
+        history.complete("/logs/a.zip");
+        history.save().unwrap();
+
+        let history = History::open(&store).unwrap();
+        let record = history.get("/logs/a.zip").unwrap();
+        assert_eq!(record.attempts, 2);
+        assert_eq!(record.status, UploadStatus::Completed);
+        assert_eq!(record.last_error, None);
+    }
+
+    #[test]
+    fn endpoints_reset_when_archive_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("history.json");
+
+        let mut history = History::open(&store).unwrap();
+        history.start_attempt("/logs/a.zip", "bucket", "a.zip", None, 3, "abc");
+        history.endpoint_finished("/logs/a.zip", "primary", Ok(()));
+        history.endpoint_finished("/logs/a.zip", "backup", Err(String::from("timeout")));
+        history.save().unwrap();
+
+        let record = History::open(&store).unwrap().get("/logs/a.zip").cloned();
+        let record = record.unwrap();
+        assert!(record.confirmed("primary"));
+        assert!(!record.confirmed("backup"));
+        assert_eq!(
+            record.endpoints["backup"].last_error.as_deref(),
+            Some("timeout")
+        );
+
+        history.start_attempt("/logs/a.zip", "bucket", "a.zip", None, 3, "abc");
+        assert!(history.get("/logs/a.zip").unwrap().confirmed("primary"));
+        history.start_attempt("/logs/a.zip", "bucket", "a.zip", None, 4, "def");
+        assert!(!history.get("/logs/a.zip").unwrap().confirmed("primary"));
+    }
+
+    #[test]
+    fn remove_unfinished_keeps_completed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = History::open(dir.path().join("history.json")).unwrap();
+
+        history.start_attempt("/logs/a.zip", "bucket", "a.zip", None, 3, "abc");
+        history.complete("/logs/a.zip");
+        history.start_attempt("/logs/b.zip", "bucket", "b.zip", None, 3, "def");
+
+        history.remove_unfinished("/logs/a.zip");
+        history.remove_unfinished("/logs/b.zip");
+
+        assert!(history.get("/logs/a.zip").is_some());
+        assert!(history.get("/logs/b.zip").is_none());
+    }
+
+    #[test]
+    fn pending_deletions_are_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("history.json");
+        let due_at = datetime!(2026-10-18 10:05 UTC);
+
+        let mut history = History::open(&store).unwrap();
+        for path in ["/logs/a.zip", "/logs/b.zip"] {
+            history.start_attempt(path, "bucket", "a.zip", None, 3, "abc");
+            history.complete(path);
+            history.schedule_deletion(path, due_at);
+        }
+        history.cancel_deletion("/logs/b.zip");
+        history.save().unwrap();
+
+        let mut history = History::open(&store).unwrap();
+        assert_eq!(
+            history.pending_deletions(),
+            vec![(String::from("/logs/a.zip"), due_at)]
+        );
+        history.mark_deleted("/logs/a.zip");
+        assert!(history.pending_deletions().is_empty());
+    }
+
+    #[test]
+    fn prunes_old_records_of_gone_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("history.json");
+        let kept = dir.path().join("kept.zip");
+        fs::write(&kept, "logga").unwrap();
+        let kept = kept.to_str().unwrap();
+
+        let mut history = History::open(&store).unwrap().with_retention(30);
+        for path in ["/logs/gone.zip", "/logs/failed.zip", "/logs/due.zip", kept] {
+            history.start_attempt(path, "bucket", "a.zip", None, 3, "abc");
+            history.complete(path);
+        }
+        history.fail("/logs/failed.zip", String::from("timeout"));
+        history.schedule_deletion("/logs/due.zip", datetime!(2026-10-18 10:05 UTC));
+        history.start_attempt("/logs/new.zip", "bucket", "a.zip", None, 3, "abc");
+        history.complete("/logs/new.zip");
+        for record in history.records.values_mut() {
+            if record.path != "/logs/new.zip" {
+                record.updated_at -= time::Duration::days(31);
+            }
+        }
+        history.save().unwrap();
+
+        let history = History::open(&store).unwrap();
+        let paths: Vec<&str> = history.records().map(|r| r.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            ["/logs/due.zip", "/logs/failed.zip", "/logs/new.zip", kept]
+        );
+        // Compact, one line
+        assert_eq!(fs::read_to_string(&store).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn corrupt_store_fails_to_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("history.json");
+        fs::write(&store, "{not json").unwrap();
+
+        assert!(History::open(&store).is_err())
+    }
+
+    #[test]
+    fn sha256_of_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.zip");
+        fs::write(&file, "logga").unwrap();
+
+        assert_eq!(
+            sha256_file(&file).unwrap(),
+            "5e381b209757854cef24139149a712acd583869579423dc718af09a7d8a587f9"
+        )
+    }
+}
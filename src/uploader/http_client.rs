@@ -0,0 +1,167 @@
+use crate::configuration::S3;
+use aws_smithy_runtime_api::client::http::{
+    http_client_fn, HttpConnector, HttpConnectorFuture, HttpConnectorSettings, SharedHttpClient,
+    SharedHttpConnector,
+};
+use aws_smithy_runtime_api::client::orchestrator::HttpRequest;
+use aws_smithy_runtime_api::client::result::ConnectorError;
+use aws_smithy_runtime_api::http::Response;
+use aws_smithy_types::body::SdkBody;
+use log::warn;
+use std::fs;
+use std::io;
+
+// Connector backed by reqwest, used instead of the SDK's default HTTPS client
+// whenever the backend needs a custom trust store, a proxy or relaxed TLS.
+#[derive(Debug, Clone)]
+struct ReqwestConnector {
+    client: reqwest::Client,
+}
+
+impl HttpConnector for ReqwestConnector {
+    fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+        let client = self.client.clone();
+
+        HttpConnectorFuture::new(async move {
+            let request = request
+                .try_into_http1x()
+                .map_err(|err| ConnectorError::other(err.into(), None))?
+                .map(reqwest::Body::wrap);
+            let request = reqwest::Request::try_from(request)
+                .map_err(|err| ConnectorError::other(err.into(), None))?;
+
+            let response = client.execute(request).await.map_err(|err| {
+                if err.is_timeout() {
+                    ConnectorError::timeout(err.into())
+                } else {
+                    ConnectorError::io(err.into())
+                }
+            })?;
+
+            let response =
+                http::Response::<reqwest::Body>::from(response).map(SdkBody::from_body_1_x);
+            Response::try_from(response).map_err(|err| ConnectorError::other(err.into(), None))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum HttpClientError {
+    CABundleReadFailed(String, io::Error),
+    InvalidCABundle(reqwest::Error),
+    InvalidProxyUrl(reqwest::Error),
+    BuildFailed(reqwest::Error),
+}
+
+impl std::fmt::Display for HttpClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            HttpClientError::CABundleReadFailed(path, err) => {
+                write!(f, "reading CA bundle {}: {}", path, err)
+            }
+            HttpClientError::InvalidCABundle(err) => write!(f, "parsing CA bundle: {}", err),
+            HttpClientError::InvalidProxyUrl(err) => write!(f, "parsing proxy url: {}", err),
+            HttpClientError::BuildFailed(err) => write!(f, "building http client: {}", err),
+        }
+    }
+}
+
+// TLS and proxy settings, kept around to build a reqwest client per connector settings.
+#[derive(Clone)]
+struct ClientOptions {
+    certificates: Vec<reqwest::Certificate>,
+    proxy: Option<reqwest::Proxy>,
+    insecure_skip_verify: bool,
+}
+
+impl ClientOptions {
+    fn build(&self, settings: &HttpConnectorSettings) -> Result<reqwest::Client, reqwest::Error> {
+        let mut builder = reqwest::Client::builder();
+        for certificate in &self.certificates {
+            builder = builder.add_root_certificate(certificate.clone());
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        if let Some(timeout) = settings.connect_timeout() {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = settings.read_timeout() {
+            builder = builder.read_timeout(timeout);
+        }
+        builder
+            .danger_accept_invalid_certs(self.insecure_skip_verify)
+            .build()
+    }
+}
+
+// Returns None when the SDK's default HTTPS client is good enough for the backend.
+pub fn build_http_client(s3: &S3) -> Result<Option<SharedHttpClient>, HttpClientError> {
+    if s3.ca_bundle_path.is_none() && s3.proxy_url.is_none() && !s3.insecure_skip_verify {
+        return Ok(None);
+    }
+
+    let mut options = ClientOptions {
+        certificates: vec![],
+        proxy: None,
+        insecure_skip_verify: s3.insecure_skip_verify,
+    };
+
+    if let Some(path) = &s3.ca_bundle_path {
+        let pem =
+            fs::read(path).map_err(|err| HttpClientError::CABundleReadFailed(path.clone(), err))?;
+        options.certificates = reqwest::Certificate::from_pem_bundle(&pem)
+            .map_err(HttpClientError::InvalidCABundle)?;
+    }
+
+    if let Some(url) = &s3.proxy_url {
+        options.proxy = Some(reqwest::Proxy::all(url).map_err(HttpClientError::InvalidProxyUrl)?);
+    }
+
+    if s3.insecure_skip_verify {
+        warn!("TLS certificate verification is disabled, do not use this in production!");
+    }
+
+    // Fail early on invalid settings instead of on the first request
+    let fallback = options
+        .build(&HttpConnectorSettings::default())
+        .map_err(HttpClientError::BuildFailed)?;
+
+    Ok(Some(http_client_fn(move |settings, _| {
+        let client = options.build(settings).unwrap_or_else(|_| fallback.clone());
+        SharedHttpConnector::new(ReqwestConnector { client })
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_http_client, HttpClientError};
+    use crate::configuration::S3;
+
+    #[test]
+    fn default_client_when_not_customized() {
+        let outcome = build_http_client(&S3::default());
+        assert!(matches!(outcome, Ok(None)))
+    }
+
+    #[test]
+    fn custom_client_with_proxy() {
+        let s3 = S3 {
+            proxy_url: Some(String::from("http://proxy.internal:3128")),
+            ..Default::default()
+        };
+        assert!(matches!(build_http_client(&s3), Ok(Some(_))))
+    }
+
+    #[test]
+    fn missing_ca_bundle() {
+        let s3 = S3 {
+            ca_bundle_path: Some(String::from("/nonexistent/ca.pem")),
+            ..Default::default()
+        };
+        assert!(matches!(
+            build_http_client(&s3),
+            Err(HttpClientError::CABundleReadFailed(_, _))
+        ))
+    }
+}
@@ -0,0 +1,115 @@
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+// Subdirectory of the watched directory receiving corrupt archives
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Intact,
+    Corrupt(String),
+    // Valid as far as we can tell, but using a compression method we can't check
+    Unverifiable(String),
+}
+
+// Only archives named *.zip are checked, other files pass as they are.
+pub fn is_zip(file_path: &Path) -> bool {
+    file_path
+        .extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+// Reads the central directory, then streams every entry through its decompressor
+// into a sink; the zip crate compares each entry's CRC-32 once it's read to the
+// end. Memory stays bounded by the central directory and the copy buffer, however
+// large the entries. Errors are about the file itself, not its content.
+pub fn verify_zip(file_path: &Path) -> io::Result<Verdict> {
+    let file = BufReader::new(File::open(file_path)?);
+    let mut archive = match ZipArchive::new(file) {
+        Ok(archive) => archive,
+        Err(err) => return Ok(verdict(err)),
+    };
+
+    for index in 0..archive.len() {
+        let mut entry = match archive.by_index(index) {
+            Ok(entry) => entry,
+            Err(err) => return Ok(verdict(err)),
+        };
+        let name = entry.name().to_string();
+        if let Err(err) = io::copy(&mut entry, &mut io::sink()) {
+            return Ok(Verdict::Corrupt(format!("{}: {}", name, err)));
+        }
+    }
+
+    Ok(Verdict::Intact)
+}
+
+fn verdict(err: ZipError) -> Verdict {
+    match err {
+        ZipError::UnsupportedArchive(reason) => Verdict::Unverifiable(reason.to_string()),
+        err => Verdict::Corrupt(err.to_string()),
+    }
+}
+
+// Moves the archive into the quarantine directory next to it, out of the watcher's sight.
+pub fn quarantine(file_path: &Path) -> io::Result<PathBuf> {
+    let dir = file_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join(QUARANTINE_DIR);
+    fs::create_dir_all(&dir)?;
+    let destination = dir.join(file_path.file_name().unwrap_or_default());
+    fs::rename(file_path, &destination)?;
+    Ok(destination)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quarantine, verify_zip, Verdict};
+    use std::fs;
+    use std::io::Write;
+    use std::path::Path;
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    fn write_zip(path: &Path) {
+        let mut zip = ZipWriter::new(fs::File::create(path).unwrap());
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        zip.start_file("access.log", options).unwrap();
+        zip.write_all(b"GET / 200\nGET /favicon.ico 404\n").unwrap();
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn detects_truncated_and_flipped_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("a.zip");
+        write_zip(&archive);
+        assert_eq!(verify_zip(&archive).unwrap(), Verdict::Intact);
+
+        // A bit flipped inside the stored entry fails its CRC
+        let mut bytes = fs::read(&archive).unwrap();
+        let offset = bytes.windows(3).position(|w| w == b"GET").unwrap();
+        bytes[offset] = b'P';
+        let flipped = dir.path().join("flipped.zip");
+        fs::write(&flipped, &bytes).unwrap();
+        assert!(matches!(verify_zip(&flipped).unwrap(), Verdict::Corrupt(_)));
+
+        // Cut mid-rotation, the central directory is missing
+        let truncated = dir.path().join("truncated.zip");
+        fs::write(&truncated, &bytes[..bytes.len() / 2]).unwrap();
+        assert!(matches!(
+            verify_zip(&truncated).unwrap(),
+            Verdict::Corrupt(_)
+        ));
+
+        let moved = quarantine(&truncated).unwrap();
+        assert_eq!(moved, dir.path().join("quarantine").join("truncated.zip"));
+        assert!(!truncated.exists());
+    }
+}
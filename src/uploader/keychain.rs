@@ -0,0 +1,62 @@
+use crate::uploader::s3_client::ClientError;
+use security_framework::passwords::get_generic_password;
+
+pub enum KeychainServices {
+    AwsAccessKeyId,
+    AwsSecretAccessKey,
+}
+
+impl From<KeychainServices> for &str {
+    fn from(key: KeychainServices) -> Self {
+        match key {
+            KeychainServices::AwsAccessKeyId => "com.logga.aws-access-key-id",
+            KeychainServices::AwsSecretAccessKey => "com.logga.aws-secret-access-key",
+        }
+    }
+}
+
+pub fn keychain_item<'a>(key: KeychainServices, user: &String) -> Result<String, ClientError<'a>> {
+    let password = get_generic_password(key.into(), user)?;
+    Ok(String::from_utf8(password)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{keychain_item, KeychainServices};
+    use crate::uploader::s3_client::ClientError;
+    use security_framework::passwords::{delete_generic_password, set_generic_password};
+
+    macro_rules! assert_err {
+        ($expression:expr, $($pattern:tt)+) => {
+            match $expression {
+                $($pattern)+ => (),
+                ref e => panic!("expected `{}` but got `{:?}`", stringify!($($pattern)+), e),
+            }
+        }
+    }
+
+    #[test]
+    fn keychain_item_fail_to_read() {
+        let user = whoami::username();
+        assert_err!(
+            keychain_item(KeychainServices::AwsAccessKeyId, &user),
+            Err(ClientError::KeychainReadFailed(_))
+        )
+    }
+
+    #[test]
+    fn keychain_item_correct_read() {
+        let user = whoami::username();
+        let expected = "topsecret";
+
+        let _ = set_generic_password(
+            KeychainServices::AwsAccessKeyId.into(),
+            &user,
+            expected.as_bytes(),
+        );
+        let outcome = keychain_item(KeychainServices::AwsAccessKeyId, &user).unwrap_or_default();
+
+        let _ = delete_generic_password(KeychainServices::AwsAccessKeyId.into(), &user);
+        assert_eq!(outcome, expected)
+    }
+}
@@ -1,2 +1,15 @@
+pub mod audit;
+mod batch;
+mod bundle;
+mod debounce;
+pub mod group;
+pub mod history;
+mod http_client;
+mod integrity;
+#[cfg(all(target_os = "macos", feature = "keychain"))]
+mod keychain;
+pub mod restore;
+pub mod route;
 pub mod s3_client;
+pub mod upload;
 pub mod watcher;
@@ -0,0 +1,361 @@
+use crate::flags::ArchiveFilter;
+use crate::template::Template;
+use crate::uploader::bundle::{self, BundleError, BundleIndex};
+use crate::uploader::history;
+use aws_sdk_s3::error::DisplayErrorContext;
+use aws_sdk_s3::Client;
+use flate2::read::GzDecoder;
+use log::{debug, info};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::{fmt, result};
+use time::{Date, OffsetDateTime};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Archive {
+    pub key: String,
+    pub size: i64,
+    pub host: Option<String>,
+    pub date: Option<Date>,
+}
+
+#[derive(Debug)]
+pub enum RestoreError {
+    List(String),
+    Download(String, String),
+    Io(String, io::Error),
+    UnsupportedEncoding(String, String),
+    Bundle(String, BundleError),
+    // Absolute or with .. components, it would be written outside the output directory
+    UnsafeKey(String),
+    ChecksumMismatch {
+        key: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RestoreError::List(err) => write!(f, "listing archives: {}", err),
+            RestoreError::Download(key, err) => write!(f, "downloading {}: {}", key, err),
+            RestoreError::Io(key, err) => write!(f, "writing {}: {}", key, err),
+            RestoreError::UnsupportedEncoding(key, encoding) => {
+                write!(f, "{} uses unsupported encoding {}", key, encoding)
+            }
+            RestoreError::Bundle(key, err) => write!(f, "unpacking bundle {}: {}", key, err),
+            RestoreError::UnsafeKey(key) => {
+                write!(
+                    f,
+                    "refusing to restore {} outside the output directory",
+                    key
+                )
+            }
+            RestoreError::ChecksumMismatch {
+                key,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "checksum mismatch for {}: expected {}, got {}",
+                key, expected, actual
+            ),
+        }
+    }
+}
+
+type Result<T> = result::Result<T, RestoreError>;
+
+// Lists the bucket and keeps the keys matching the key template and the filter.
+// Host and date come from the key itself; when the template doesn't encode them the
+// object's host metadata and last modification date are used instead.
+pub async fn list_archives(
+    client: &Client,
+    bucket: &str,
+    key_template: &Template,
+    filter: &ArchiveFilter,
+) -> Result<Vec<Archive>> {
+    let prefix = filter
+        .prefix
+        .clone()
+        .unwrap_or_else(|| key_template.static_prefix().to_string());
+
+    let mut pages = client
+        .list_objects_v2()
+        .bucket(bucket)
+        .prefix(prefix)
+        .into_paginator()
+        .send();
+
+    let mut archives = vec![];
+    while let Some(page) = pages.next().await {
+        let page = page.map_err(|err| RestoreError::List(DisplayErrorContext(err).to_string()))?;
+
+        for object in page.contents() {
+            let key = object.key().unwrap_or_default();
+            // Restored along with their bundle
+            if key.ends_with(bundle::INDEX_SUFFIX) {
+                continue;
+            }
+            let captures = match key_template.parse_rendered(key) {
+                Some(captures) => captures,
+                None => {
+                    debug!("skipping {}, doesn't match key template", key);
+                    continue;
+                }
+            };
+
+            let date = captures.date.or_else(|| {
+                object
+                    .last_modified()
+                    .and_then(|dt| OffsetDateTime::from_unix_timestamp(dt.secs()).ok())
+                    .map(|dt| dt.date())
+            });
+            if filter
+                .from
+                .is_some_and(|from| date.is_some_and(|d| d < from))
+                || filter.to.is_some_and(|to| date.is_some_and(|d| d > to))
+            {
+                continue;
+            }
+
+            let mut host = captures.host;
+            if host.is_none() && filter.host.is_some() {
+                host = object_host(client, bucket, key).await?;
+            }
+            if filter.host.is_some() && filter.host != host {
+                continue;
+            }
+
+            archives.push(Archive {
+                key: key.to_string(),
+                size: object.size().unwrap_or_default(),
+                host,
+                date,
+            });
+        }
+    }
+
+    Ok(archives)
+}
+
+async fn object_host(client: &Client, bucket: &str, key: &str) -> Result<Option<String>> {
+    let head = client
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|err| {
+            RestoreError::Download(key.to_string(), DisplayErrorContext(err).to_string())
+        })?;
+
+    Ok(head.metadata().and_then(|md| md.get("host")).cloned())
+}
+
+// Downloads the archives into output_dir, keeping the key's directory structure.
+// Bundles are unpacked next to where their tar would go. Returns the number of
+// restored archives, failing on the first broken download.
+pub async fn restore(
+    client: &Client,
+    bucket: &str,
+    archives: Vec<Archive>,
+    output_dir: &Path,
+    concurrency: usize,
+) -> Result<usize> {
+    // Keys come from the bucket, none may write outside output_dir
+    if let Some(archive) = archives.iter().find(|archive| !is_relative(&archive.key)) {
+        return Err(RestoreError::UnsafeKey(archive.key.clone()));
+    }
+
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut downloads = JoinSet::new();
+    let mut keys = HashMap::new();
+
+    for archive in archives {
+        let client = client.clone();
+        let bucket = bucket.to_string();
+        let destination = output_dir.join(&archive.key);
+        let semaphore = semaphore.clone();
+        let key = archive.key.clone();
+
+        let task = downloads.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            download(&client, &bucket, &key, &destination).await
+        });
+        keys.insert(task.id(), archive.key);
+    }
+
+    let mut restored = 0;
+    while let Some(outcome) = downloads.join_next().await {
+        match outcome {
+            Ok(Ok(archives)) => restored += archives,
+            Ok(Err(err)) => {
+                downloads.abort_all();
+                return Err(err);
+            }
+            Err(err) => {
+                let key = keys.remove(&err.id()).unwrap_or_default();
+                return Err(RestoreError::Download(key, err.to_string()));
+            }
+        }
+    }
+
+    Ok(restored)
+}
+
+async fn download(client: &Client, bucket: &str, key: &str, destination: &Path) -> Result<usize> {
+    let io_err = |err| RestoreError::Io(key.to_string(), err);
+
+    let object = client
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|err| {
+            RestoreError::Download(key.to_string(), DisplayErrorContext(err).to_string())
+        })?;
+
+    let encoding = object.content_encoding().map(str::to_string);
+    let expected = object.metadata().and_then(|md| md.get("sha256")).cloned();
+    let is_bundle = object
+        .metadata()
+        .is_some_and(|md| md.contains_key(bundle::BUNDLE_METADATA));
+
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).map_err(io_err)?;
+    }
+    let partial = with_suffix(destination, ".part");
+    let mut file = tokio::fs::File::create(&partial).await.map_err(io_err)?;
+    let mut body = object.body.into_async_read();
+    tokio::io::copy(&mut body, &mut file)
+        .await
+        .map_err(io_err)?;
+    file.sync_all().await.map_err(io_err)?;
+
+    // Undo the transforms applied on upload, the checksum covers the original archive
+    match encoding.as_deref() {
+        None | Some("") | Some("identity") => fs::rename(&partial, destination).map_err(io_err)?,
+        Some("gzip") => {
+            let mut decoder = GzDecoder::new(BufReader::new(File::open(&partial).map_err(io_err)?));
+            let mut output = File::create(destination).map_err(io_err)?;
+            io::copy(&mut decoder, &mut output).map_err(io_err)?;
+            fs::remove_file(&partial).map_err(io_err)?;
+        }
+        Some(other) => {
+            let _ = fs::remove_file(&partial);
+            return Err(RestoreError::UnsupportedEncoding(
+                key.to_string(),
+                other.to_string(),
+            ));
+        }
+    }
+
+    if let Some(expected) = expected {
+        let actual = history::sha256_file(destination).map_err(io_err)?;
+        if actual != expected {
+            let _ = fs::remove_file(destination);
+            return Err(RestoreError::ChecksumMismatch {
+                key: key.to_string(),
+                expected,
+                actual,
+            });
+        }
+    }
+
+    if is_bundle {
+        let index = bundle_index(client, bucket, key).await?;
+        let dir = destination.parent().unwrap_or(Path::new("."));
+        let restored = bundle::unpack(destination, dir, &index)
+            .map_err(|err| RestoreError::Bundle(key.to_string(), err))?;
+        fs::remove_file(destination).map_err(io_err)?;
+        info!("restored {} archives of {} to {:?}", restored, key, dir);
+        return Ok(restored);
+    }
+
+    info!("restored {} to {:?}", key, destination);
+    Ok(1)
+}
+
+async fn bundle_index(client: &Client, bucket: &str, key: &str) -> Result<BundleIndex> {
+    let index_key = format!("{}{}", key, bundle::INDEX_SUFFIX);
+    let download_err = |err: String| RestoreError::Download(index_key.clone(), err);
+
+    let object = client
+        .get_object()
+        .bucket(bucket)
+        .key(&index_key)
+        .send()
+        .await
+        .map_err(|err| download_err(DisplayErrorContext(err).to_string()))?;
+    let body = object
+        .body
+        .collect()
+        .await
+        .map_err(|err| download_err(err.to_string()))?;
+
+    serde_json::from_slice(&body.into_bytes()).map_err(|err| download_err(err.to_string()))
+}
+
+// Only plain names, no root, prefix, . or .. components.
+pub fn is_relative(key: &str) -> bool {
+    let path = Path::new(key);
+    path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_relative, restore, Archive, RestoreError};
+    use crate::testing::s3_client;
+
+    #[test]
+    fn keys_stay_inside_the_output_dir() {
+        assert!(is_relative("web-01/2026/10/access-1.zip"));
+        for key in [
+            "",
+            "/etc/cron.d/x",
+            "../x.zip",
+            "logs/../../x.zip",
+            "./x.zip",
+        ] {
+            assert!(!is_relative(key), "{}", key);
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_unsafe_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = |key: &str| Archive {
+            key: key.to_string(),
+            size: 3,
+            host: None,
+            date: None,
+        };
+        let archives = vec![archive("a.zip"), archive("../../etc/cron.d/x")];
+
+        // Refused before anything is downloaded
+        let client = s3_client("http://127.0.0.1:9");
+        match restore(&client, "logs", archives, dir.path(), 2).await {
+            Err(RestoreError::UnsafeKey(key)) => assert_eq!(key, "../../etc/cron.d/x"),
+            outcome => panic!("expected an unsafe key, got {:?}", outcome),
+        }
+    }
+}
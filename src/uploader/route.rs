@@ -0,0 +1,288 @@
+use crate::configuration::{AfterUpload, Batch, Watch, S3};
+use crate::template::{Template, TemplateError};
+use aws_sdk_s3::types::StorageClass;
+use glob::{Pattern, PatternError};
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum RouteError {
+    Pattern(String, PatternError),
+    KeyTemplate(String, TemplateError),
+    UnknownStorageClass(String),
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RouteError::Pattern(pattern, err) => write!(f, "invalid pattern {}: {}", pattern, err),
+            RouteError::KeyTemplate(path, err) => {
+                write!(f, "invalid key template for {}: {}", path, err)
+            }
+            RouteError::UnknownStorageClass(class) => write!(
+                f,
+                "unknown storage class {}, expected one of {}",
+                class,
+                StorageClass::values().join(", ")
+            ),
+        }
+    }
+}
+
+// Where the archives of a watch go, with the s3 section's defaults filled in.
+pub struct Target {
+    pub bucket: String,
+    pub prefix: String,
+    pub key_template: Template,
+    storage_class: Option<StorageClass>,
+    storage_class_rules: Vec<ClassRule>,
+}
+
+struct ClassRule {
+    storage_class: StorageClass,
+    min_age: Option<Duration>,
+    min_size: Option<u64>,
+}
+
+impl Target {
+    // The class of the first rule matching the archive, else the destination's.
+    pub fn storage_class(&self, size: u64, age: Duration) -> Option<&StorageClass> {
+        self.storage_class_rules
+            .iter()
+            .find(|rule| {
+                rule.min_age.is_none_or(|min_age| age >= min_age)
+                    && rule.min_size.is_none_or(|min_size| size >= min_size)
+            })
+            .map(|rule| &rule.storage_class)
+            .or(self.storage_class.as_ref())
+    }
+}
+
+pub struct Route {
+    // Resolved, events carry absolute paths
+    pub dir: PathBuf,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    pub target: Target,
+    pub after_upload: AfterUpload,
+    pub batch: Option<Batch>,
+}
+
+impl Route {
+    pub fn build(watch: &Watch, s3: &S3) -> Result<Route, RouteError> {
+        let destination = &watch.destination;
+        let key_template = destination
+            .key_template
+            .as_ref()
+            .unwrap_or(&s3.key_template);
+
+        Ok(Route {
+            dir: Path::new(&watch.path)
+                .canonicalize()
+                .unwrap_or_else(|_| PathBuf::from(&watch.path)),
+            include: patterns(&watch.include)?,
+            exclude: patterns(&watch.exclude)?,
+            target: Target {
+                bucket: destination
+                    .bucket
+                    .clone()
+                    .unwrap_or_else(|| s3.bucket.clone()),
+                prefix: destination.prefix.clone(),
+                key_template: Template::parse(key_template)
+                    .map_err(|err| RouteError::KeyTemplate(watch.path.clone(), err))?,
+                storage_class: destination
+                    .storage_class
+                    .as_deref()
+                    .or(s3.storage_class.as_deref())
+                    .map(storage_class)
+                    .transpose()?,
+                storage_class_rules: destination
+                    .storage_class_rules
+                    .iter()
+                    .map(|rule| {
+                        Ok(ClassRule {
+                            storage_class: storage_class(&rule.storage_class)?,
+                            min_age: rule
+                                .min_age_days
+                                .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+                            min_size: rule.min_size_bytes,
+                        })
+                    })
+                    .collect::<Result<_, RouteError>>()?,
+            },
+            after_upload: watch.after_upload.clone(),
+            batch: watch.batch.clone(),
+        })
+    }
+
+    // Files directly inside dir, matching an include and no exclude pattern.
+    pub fn matches(&self, file_path: &Path) -> bool {
+        if file_path.parent() != Some(self.dir.as_path()) {
+            return false;
+        }
+        let name = file_path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .unwrap_or_default();
+
+        self.include.iter().any(|pattern| pattern.matches(name))
+            && !self.exclude.iter().any(|pattern| pattern.matches(name))
+    }
+}
+
+// Only the classes the SDK knows about, a typo would otherwise fail every upload.
+fn storage_class(class: &str) -> Result<StorageClass, RouteError> {
+    if !StorageClass::values().contains(&class) {
+        return Err(RouteError::UnknownStorageClass(class.to_string()));
+    }
+    Ok(StorageClass::from(class))
+}
+
+fn patterns(patterns: &[String]) -> Result<Vec<Pattern>, RouteError> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Pattern::new(pattern).map_err(|err| RouteError::Pattern(pattern.clone(), err))
+        })
+        .collect()
+}
+
+// Routes archives to the first watch matching them.
+pub struct Router {
+    routes: Vec<Arc<Route>>,
+}
+
+impl Router {
+    pub fn new(routes: Vec<Route>) -> Router {
+        Router {
+            routes: routes.into_iter().map(Arc::new).collect(),
+        }
+    }
+
+    pub fn route(&self, file_path: &Path) -> Option<Arc<Route>> {
+        self.routes
+            .iter()
+            .find(|route| route.matches(file_path))
+            .cloned()
+    }
+
+    // Each directory once, several watches may share one with different patterns.
+    pub fn dirs(&self) -> Vec<&Path> {
+        let mut dirs: Vec<&Path> = vec![];
+        for route in &self.routes {
+            if !dirs.contains(&route.dir.as_path()) {
+                dirs.push(&route.dir);
+            }
+        }
+        dirs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Route, RouteError, Router};
+    use crate::configuration::{Destination, StorageClassRule, Watch, S3};
+    use aws_sdk_s3::types::StorageClass;
+    use std::path::Path;
+    use std::time::Duration;
+
+    fn watch(path: &str, include: &[&str], exclude: &[&str], bucket: Option<&str>) -> Watch {
+        Watch {
+            path: path.to_string(),
+            include: include.iter().map(|p| p.to_string()).collect(),
+            exclude: exclude.iter().map(|p| p.to_string()).collect(),
+            destination: Destination {
+                bucket: bucket.map(str::to_string),
+                ..Default::default()
+            },
+            after_upload: Default::default(),
+            batch: None,
+        }
+    }
+
+    fn s3() -> S3 {
+        S3 {
+            bucket: String::from("default"),
+            key_template: String::from("{filename}"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn first_matching_watch_wins() {
+        let router = Router::new(vec![
+            Route::build(&watch("/logs", &["app1-*.zip"], &[], Some("app1")), &s3()).unwrap(),
+            Route::build(&watch("/logs", &["*.zip"], &["*.tmp.zip"], None), &s3()).unwrap(),
+            Route::build(&watch("/other", &["*.zip"], &[], Some("other")), &s3()).unwrap(),
+        ]);
+
+        let bucket = |path: &str| {
+            router
+                .route(Path::new(path))
+                .map(|route| route.target.bucket.clone())
+        };
+        assert_eq!(bucket("/logs/app1-1.zip"), Some(String::from("app1")));
+        assert_eq!(bucket("/logs/app2-1.zip"), Some(String::from("default")));
+        assert_eq!(bucket("/logs/app2-1.tmp.zip"), None);
+        assert_eq!(bucket("/logs/notes.txt"), None);
+        assert_eq!(bucket("/logs/nested/app1-1.zip"), None);
+        assert_eq!(bucket("/other/a.zip"), Some(String::from("other")));
+        assert_eq!(router.dirs(), vec![Path::new("/logs"), Path::new("/other")]);
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        assert!(Route::build(&watch("/logs", &["[*.zip"], &[], None), &s3()).is_err())
+    }
+
+    #[test]
+    fn storage_class_by_age_and_size() {
+        let mut watch = watch("/logs", &["*.zip"], &[], None);
+        watch.destination.storage_class = Some(String::from("STANDARD_IA"));
+        watch.destination.storage_class_rules = vec![
+            StorageClassRule {
+                storage_class: String::from("DEEP_ARCHIVE"),
+                min_age_days: Some(90),
+                min_size_bytes: None,
+            },
+            StorageClassRule {
+                storage_class: String::from("GLACIER_IR"),
+                min_age_days: None,
+                min_size_bytes: Some(1024),
+            },
+        ];
+        let target = Route::build(&watch, &s3()).unwrap().target;
+        let days = |days: u64| Duration::from_secs(days * 24 * 60 * 60);
+
+        assert_eq!(
+            target.storage_class(10, days(100)),
+            Some(&StorageClass::DeepArchive)
+        );
+        assert_eq!(
+            target.storage_class(2048, days(1)),
+            Some(&StorageClass::GlacierIr)
+        );
+        assert_eq!(
+            target.storage_class(10, days(1)),
+            Some(&StorageClass::StandardIa)
+        );
+    }
+
+    #[test]
+    fn unknown_storage_class() {
+        let mut watch = watch("/logs", &["*.zip"], &[], None);
+        watch.destination.storage_class_rules = vec![StorageClassRule {
+            storage_class: String::from("GLACIER_INSTANT"),
+            min_age_days: Some(30),
+            min_size_bytes: None,
+        }];
+
+        assert!(matches!(
+            Route::build(&watch, &s3()),
+            Err(RouteError::UnknownStorageClass(class)) if class == "GLACIER_INSTANT"
+        ));
+    }
+}
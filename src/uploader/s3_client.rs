@@ -1,16 +1,19 @@
-use crate::configuration::Configuration;
+use crate::configuration::{Configuration, Endpoint, S3};
+use crate::uploader::http_client::{self, HttpClientError};
+#[cfg(all(target_os = "macos", feature = "keychain"))]
+use crate::uploader::keychain::{keychain_item, KeychainServices};
 use aws_config::meta::region::RegionProviderChain;
 use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
 use aws_sdk_s3::Client;
-use log::debug;
+#[cfg(all(target_os = "macos", feature = "keychain"))]
 use security_framework::base::Error;
-use security_framework::passwords::get_generic_password;
 use std::env::VarError;
+#[cfg(all(target_os = "macos", feature = "keychain"))]
 use std::string::FromUtf8Error;
 use std::{env, fmt};
-use whoami;
 
 #[derive(Debug, Eq, Hash, PartialEq)]
+#[allow(clippy::enum_variant_names)]
 enum EnvVar {
     AwsAccessKeyId,
     AwsSecretAccessKey,
@@ -27,25 +30,16 @@ impl From<EnvVar> for &str {
     }
 }
 
-enum KeychainServices {
-    AwsAccessKeyId,
-    AwsSecretAccessKey,
-}
-
-impl From<KeychainServices> for &str {
-    fn from(key: KeychainServices) -> Self {
-        match key {
-            KeychainServices::AwsAccessKeyId => "com.logga.aws-access-key-id",
-            KeychainServices::AwsSecretAccessKey => "com.logga.aws-secret-access-key",
-        }
-    }
-}
-
 #[derive(Debug)]
 pub enum ClientError<'a> {
     CredentialNotSet(&'a str, VarError),
+    #[cfg(all(target_os = "macos", feature = "keychain"))]
     KeychainReadFailed(Error),
+    #[cfg(all(target_os = "macos", feature = "keychain"))]
     KeychainPasswordParseFailed(std::string::FromUtf8Error),
+    // keychainAuthentication is set but the helper was built without Keychain support
+    KeychainUnsupported,
+    HttpClient(HttpClientError),
 }
 
 impl fmt::Display for ClientError<'_> {
@@ -54,50 +48,99 @@ impl fmt::Display for ClientError<'_> {
             ClientError::CredentialNotSet(key, err) => {
                 write!(f, "{} {}", key, err)
             }
+            #[cfg(all(target_os = "macos", feature = "keychain"))]
             ClientError::KeychainReadFailed(err) => write!(f, "{}", err),
+            #[cfg(all(target_os = "macos", feature = "keychain"))]
             ClientError::KeychainPasswordParseFailed(err) => write!(f, "{}", err),
+            ClientError::KeychainUnsupported => {
+                write!(f, "keychain authentication is only supported on macOS")
+            }
+            ClientError::HttpClient(err) => write!(f, "{}", err),
         }
     }
 }
 
+#[cfg(all(target_os = "macos", feature = "keychain"))]
 impl From<Error> for ClientError<'_> {
     fn from(err: Error) -> Self {
-        match err {
-            err => ClientError::KeychainReadFailed(err),
-        }
+        ClientError::KeychainReadFailed(err)
     }
 }
 
+#[cfg(all(target_os = "macos", feature = "keychain"))]
 impl From<FromUtf8Error> for ClientError<'_> {
     fn from(err: FromUtf8Error) -> Self {
-        match err {
-            err => ClientError::KeychainPasswordParseFailed(err),
-        }
+        ClientError::KeychainPasswordParseFailed(err)
     }
 }
 
-pub async fn create_s3_client(config: &Configuration) -> Result<Client, ClientError> {
-    let region = env_var(EnvVar::AwsDefaultRegion).unwrap_or_else(|_| config.s3.region.clone());
-    let region_provider = Region::new(region);
+impl From<HttpClientError> for ClientError<'_> {
+    fn from(err: HttpClientError) -> Self {
+        ClientError::HttpClient(err)
+    }
+}
 
-    let credentials_store = get_aws_credentials(&config)?;
+// Dry runs never send a request, their clients are built without credentials.
+pub async fn create_s3_client(
+    config: &Configuration,
+    dry_run: bool,
+) -> Result<Client, ClientError<'_>> {
+    let region = env_var(EnvVar::AwsDefaultRegion).unwrap_or_else(|_| config.s3.region.clone());
+    create_client(config, &config.s3, region, dry_run).await
+}
 
-    let credentials = Credentials::new(
-        credentials_store.0,
-        credentials_store.1,
-        None,
-        None,
-        "s3_compatible_backend",
-    );
+// A client for one endpoint of the destination group, with the s3 section's
+// credentials, TLS and proxy settings.
+pub async fn create_endpoint_client<'a>(
+    config: &'a Configuration,
+    endpoint: &Endpoint,
+    dry_run: bool,
+) -> Result<Client, ClientError<'a>> {
+    let region = match &endpoint.region {
+        Some(region) => region.clone(),
+        None => env_var(EnvVar::AwsDefaultRegion).unwrap_or_else(|_| config.s3.region.clone()),
+    };
+    let s3 = S3 {
+        endpoint: endpoint.endpoint.clone(),
+        force_path_style: endpoint
+            .force_path_style
+            .unwrap_or(config.s3.force_path_style),
+        ..config.s3.clone()
+    };
+    create_client(config, &s3, region, dry_run).await
+}
 
+async fn create_client<'a>(
+    config: &'a Configuration,
+    s3: &S3,
+    region: String,
+    dry_run: bool,
+) -> Result<Client, ClientError<'a>> {
+    let region_provider = Region::new(region);
     let region_provider = RegionProviderChain::default_provider().or_else(region_provider);
-    let shared_config = aws_config::defaults(BehaviorVersion::v2023_11_09())
-        .region(region_provider)
-        .credentials_provider(credentials)
-        .load()
-        .await;
+    // Newer behavior versions abort slow uploads with stalled stream protection
+    #[allow(deprecated)]
+    let mut loader = aws_config::defaults(BehaviorVersion::v2023_11_09()).region(region_provider);
+
+    if dry_run {
+        loader = loader.no_credentials();
+    } else {
+        let credentials_store = get_aws_credentials(config)?;
+        loader = loader.credentials_provider(Credentials::new(
+            credentials_store.0,
+            credentials_store.1,
+            None,
+            None,
+            "s3_compatible_backend",
+        ));
+    }
+    if let Some(http_client) = http_client::build_http_client(s3)? {
+        loader = loader.http_client(http_client);
+    }
+    let shared_config = loader.load().await;
     let shared_config_with_endpoint = aws_sdk_s3::config::Builder::from(&shared_config)
-        .endpoint_url(&config.s3.endpoint)
+        .endpoint_url(&s3.endpoint)
+        .force_path_style(s3.force_path_style)
         .build();
 
     Ok(Client::from_conf(shared_config_with_endpoint))
@@ -108,23 +151,13 @@ fn env_var<'a>(key: EnvVar) -> Result<String, ClientError<'a>> {
     env::var(key_str).map_err(|e| ClientError::CredentialNotSet(key_str, e))
 }
 
-fn keychain_item<'a>(key: KeychainServices, user: &String) -> Result<String, ClientError<'a>> {
-    let password = get_generic_password(key.into(), &user)?;
-    Ok(String::from_utf8(password)?)
-}
-
 #[derive(Debug)]
 
 struct CredentialsStore(String, String);
 
 fn get_aws_credentials<'a>(config: &Configuration) -> Result<CredentialsStore, ClientError<'a>> {
     if config.s3.keychain_authentication {
-        debug!("Trying to read AWS credentials from Keychain.");
-
-        let user = whoami::username();
-        let access_key_id = keychain_item(KeychainServices::AwsAccessKeyId.into(), &user)?;
-        let secret_key = keychain_item(KeychainServices::AwsSecretAccessKey, &user)?;
-        return Ok(CredentialsStore(access_key_id, secret_key));
+        return keychain_credentials();
     }
 
     Ok(CredentialsStore(
@@ -133,15 +166,33 @@ fn get_aws_credentials<'a>(config: &Configuration) -> Result<CredentialsStore, C
     ))
 }
 
+#[cfg(all(target_os = "macos", feature = "keychain"))]
+fn keychain_credentials<'a>() -> Result<CredentialsStore, ClientError<'a>> {
+    log::debug!("Trying to read AWS credentials from Keychain.");
+
+    let user = whoami::username();
+    let access_key_id = keychain_item(KeychainServices::AwsAccessKeyId, &user)?;
+    let secret_key = keychain_item(KeychainServices::AwsSecretAccessKey, &user)?;
+    Ok(CredentialsStore(access_key_id, secret_key))
+}
+
+#[cfg(not(all(target_os = "macos", feature = "keychain")))]
+fn keychain_credentials<'a>() -> Result<CredentialsStore, ClientError<'a>> {
+    Err(ClientError::KeychainUnsupported)
+}
+
 #[cfg(test)]
 mod tests {
     use std::env;
 
+    #[cfg(all(target_os = "macos", feature = "keychain"))]
     use security_framework::passwords::{delete_generic_password, set_generic_password};
 
+    #[cfg(all(target_os = "macos", feature = "keychain"))]
+    use crate::uploader::keychain::KeychainServices;
     use crate::{
         configuration::{Configuration, S3},
-        s3_client::{self, ClientError, CredentialsStore, KeychainServices},
+        uploader::s3_client::{self, ClientError, CredentialsStore},
     };
 
     use super::EnvVar;
@@ -159,7 +210,7 @@ mod tests {
     fn env_var() {
         let expected = "logga";
         let key_str: &str = EnvVar::AwsAccessKeyId.into();
-        env::set_var(key_str, &expected);
+        env::set_var(key_str, expected);
         let outcome = match s3_client::env_var(EnvVar::AwsAccessKeyId) {
             Ok(val) => val == expected,
             _ => false,
@@ -168,32 +219,6 @@ mod tests {
         assert!(outcome)
     }
 
-    #[test]
-    fn keychain_item_fail_to_read() {
-        let user = whoami::username();
-        assert_err!(
-            s3_client::keychain_item(KeychainServices::AwsAccessKeyId, &user),
-            Err(ClientError::KeychainReadFailed(_))
-        )
-    }
-
-    #[test]
-    fn keychain_item_correct_read() {
-        let user = whoami::username();
-        let expected = "topsecret";
-
-        let _ = set_generic_password(
-            KeychainServices::AwsAccessKeyId.into(),
-            &user,
-            &expected.as_bytes(),
-        );
-        let outcome =
-            s3_client::keychain_item(KeychainServices::AwsAccessKeyId, &user).unwrap_or_default();
-
-        let _ = delete_generic_password(KeychainServices::AwsAccessKeyId.into(), &user);
-        assert_eq!(outcome, expected)
-    }
-
     #[test]
     fn env_var_missing() {
         let outcome = s3_client::env_var(EnvVar::AwsAccessKeyId);
@@ -217,7 +242,9 @@ mod tests {
                 region: String::from("value"),
                 endpoint: String::from("value"),
                 keychain_authentication: false,
+                ..Default::default()
             },
+            ..Default::default()
         }) {
             Ok(val) => val.0 == expected_creds.0 && val.1 == expected_creds.1,
             _ => false,
@@ -227,6 +254,7 @@ mod tests {
         assert!(outcome)
     }
 
+    #[cfg(all(target_os = "macos", feature = "keychain"))]
     #[test]
     fn get_aws_credentials_keychain() {
         let expected_creds = CredentialsStore("abc".to_string(), "123".to_string());
@@ -235,13 +263,13 @@ mod tests {
         let _ = set_generic_password(
             KeychainServices::AwsAccessKeyId.into(),
             &user,
-            &expected_creds.0.as_bytes(),
+            expected_creds.0.as_bytes(),
         );
 
         let _ = set_generic_password(
             KeychainServices::AwsSecretAccessKey.into(),
             &user,
-            &expected_creds.1.as_bytes(),
+            expected_creds.1.as_bytes(),
         );
 
         let outcome = match s3_client::get_aws_credentials(&Configuration {
@@ -250,7 +278,9 @@ mod tests {
                 region: String::from("value"),
                 endpoint: String::from("value"),
                 keychain_authentication: true,
+                ..Default::default()
             },
+            ..Default::default()
         }) {
             Ok(val) => val.0 == expected_creds.0 && val.1 == expected_creds.1,
             _ => false,
@@ -268,9 +298,11 @@ mod tests {
                 region: String::from("us-east-1"),
                 endpoint: String::from("s3://endpoint"),
                 keychain_authentication: false,
+                ..Default::default()
             },
+            ..Default::default()
         };
-        let outcome = s3_client::create_s3_client(&config).await;
+        let outcome = s3_client::create_s3_client(config, false).await;
 
         assert_err!(
             outcome,
@@ -278,6 +310,23 @@ mod tests {
         )
     }
 
+    #[tokio::test]
+    async fn create_s3_client_dry_run_without_credentials() {
+        let config = &Configuration {
+            s3: S3 {
+                bucket: String::from("dummy"),
+                region: String::from("us-east-1"),
+                endpoint: String::from("s3://endpoint"),
+                keychain_authentication: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert!(s3_client::create_s3_client(config, true).await.is_ok());
+    }
+
+    #[cfg(all(target_os = "macos", feature = "keychain"))]
     #[tokio::test]
     async fn create_s3_client_failing_keychain() {
         let config = &Configuration {
@@ -286,13 +335,16 @@ mod tests {
                 region: String::from("us-east-1"),
                 endpoint: String::from("s3://endpoint"),
                 keychain_authentication: true,
+                ..Default::default()
             },
+            ..Default::default()
         };
-        let outcome = s3_client::create_s3_client(&config).await;
+        let outcome = s3_client::create_s3_client(config, false).await;
 
         assert_err!(outcome, Err(ClientError::KeychainReadFailed(_)))
     }
 
+    #[cfg(all(target_os = "macos", feature = "keychain"))]
     #[tokio::test]
     async fn create_s3_client() {
         let expected_creds = CredentialsStore("abc".to_string(), "123".to_string());
@@ -301,13 +353,13 @@ mod tests {
         let _ = set_generic_password(
             KeychainServices::AwsAccessKeyId.into(),
             &user,
-            &expected_creds.0.as_bytes(),
+            expected_creds.0.as_bytes(),
         );
 
         let _ = set_generic_password(
             KeychainServices::AwsSecretAccessKey.into(),
             &user,
-            &expected_creds.1.as_bytes(),
+            expected_creds.1.as_bytes(),
         );
 
         let config = &Configuration {
@@ -316,16 +368,29 @@ mod tests {
                 region: String::from("us-east-1"),
                 endpoint: String::from("s3://endpoint"),
                 keychain_authentication: true,
+                ..Default::default()
             },
+            ..Default::default()
         };
-        let outcome = match s3_client::create_s3_client(&config).await {
-            Ok(_) => true,
-            _ => false,
-        };
+        let outcome = s3_client::create_s3_client(config, false).await.is_ok();
 
         let _ = delete_generic_password(KeychainServices::AwsAccessKeyId.into(), &user);
         let _ = delete_generic_password(KeychainServices::AwsSecretAccessKey.into(), &user);
 
         assert!(outcome);
     }
+
+    #[cfg(not(all(target_os = "macos", feature = "keychain")))]
+    #[test]
+    fn get_aws_credentials_keychain_unsupported() {
+        let outcome = s3_client::get_aws_credentials(&Configuration {
+            s3: S3 {
+                keychain_authentication: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        assert_err!(outcome, Err(ClientError::KeychainUnsupported))
+    }
 }
@@ -0,0 +1,1071 @@
+use crate::configuration::{AfterUpload, DeletionMode, GroupMode, Integrity};
+use crate::dry_run::{self, DryRunEvent};
+use crate::health::Health;
+use crate::template::TemplateContext;
+use crate::uploader::audit::{AuditEntry, AuditLog};
+use crate::uploader::bundle::{self, BundleIndex, Member};
+use crate::uploader::group::{Endpoint, EndpointGroup};
+use crate::uploader::history::{self, History, UploadRecord, UploadStatus};
+use crate::uploader::integrity::{self, Verdict};
+use crate::uploader::route::Route;
+use aws_sdk_s3::error::DisplayErrorContext;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{StorageClass, Tag, Tagging};
+use aws_sdk_s3::Client;
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{env, process};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::task;
+
+const DELETED_TAG: &str = "logga-deleted";
+// URL encoded tag set of archives uploaded despite failing their integrity check
+const CORRUPT_TAG: &str = "corrupt=true";
+
+// Shared between the upload tasks spawned by the watcher.
+pub struct Uploader {
+    group: EndpointGroup,
+    history: Mutex<History>,
+    // Number of the last history snapshot written
+    written: tokio::sync::Mutex<u64>,
+    integrity: Integrity,
+    health: Arc<Health>,
+    dry_run: bool,
+}
+
+impl Uploader {
+    pub fn new(
+        group: EndpointGroup,
+        history: History,
+        integrity: Integrity,
+        health: Arc<Health>,
+        dry_run: bool,
+    ) -> Uploader {
+        health.set_quarantined(quarantined(&history));
+        Uploader {
+            group,
+            history: Mutex::new(history),
+            written: tokio::sync::Mutex::new(0),
+            integrity,
+            health,
+            dry_run,
+        }
+    }
+
+    pub async fn upload(&self, file_path: &Path, route: &Route) {
+        let target = &route.target;
+        let path_str = file_path.to_str().unwrap_or_default();
+        let file_metadata = fs::metadata(file_path);
+        let size = file_metadata
+            .as_ref()
+            .map(|md| md.len())
+            .unwrap_or_default();
+        // Archives are dated by their rotation time, falling back to now
+        let rotated_at = file_metadata
+            .and_then(|md| md.modified())
+            .map(OffsetDateTime::from)
+            .unwrap_or_else(|_| OffsetDateTime::now_utc());
+        let file_name = file_path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .unwrap_or_default();
+        let key = object_key(route, file_name, rotated_at);
+        let key = key.as_str();
+        let age = (OffsetDateTime::now_utc() - rotated_at)
+            .try_into()
+            .unwrap_or_default();
+        let storage_class = target.storage_class(size, age);
+        let checksum = match blocking(file_path, |path| history::sha256_file(&path)).await {
+            Ok(checksum) => checksum,
+            Err(err) => {
+                error!("Problem reading {:?}: {}", file_path, err);
+                return;
+            }
+        };
+        let metadata = object_metadata(file_path, &checksum);
+
+        let corruption = self.corruption(file_path).await;
+        if let Some(reason) = &corruption {
+            if !self.integrity.upload_corrupt {
+                self.quarantine(file_path, &target.bucket, key, size, &checksum, reason)
+                    .await;
+                return;
+            }
+            warn!(
+                "{:?} is corrupt, uploading it anyway: {}",
+                file_path, reason
+            );
+        }
+
+        if self.dry_run {
+            for endpoint in self.group.pending(None) {
+                dry_run::report(&DryRunEvent::Upload {
+                    path: path_str,
+                    endpoint: self.endpoint_name(endpoint),
+                    bucket: endpoint.bucket(&target.bucket),
+                    key,
+                    size,
+                    storage_class: storage_class.map(StorageClass::as_str),
+                    metadata: &metadata,
+                });
+                if self.group.mode() == GroupMode::Failover {
+                    break;
+                }
+            }
+            return;
+        }
+
+        // Written with the upload's outcome, a crash meanwhile leaves the archive to a rescan
+        self.update(|history| {
+            history.start_attempt(path_str, &target.bucket, key, None, size, &checksum);
+            if let Some(reason) = &corruption {
+                history.mark_corrupt(path_str, reason.clone());
+            }
+        });
+
+        let object = Object {
+            bucket: &target.bucket,
+            key,
+            metadata,
+            storage_class,
+            corrupt: corruption.is_some(),
+            index: None,
+        };
+        match self.send(&[path_str], file_path, &object).await {
+            Outcome::Failed => {}
+            Outcome::Satisfied => info!(
+                "{:?} backed up, the remaining endpoints catch up later",
+                file_path
+            ),
+            Outcome::Complete => {
+                info!("{:?} backed up successfully", file_path);
+                if let Err(err) = after_upload(file_path, &route.after_upload) {
+                    error!("Problem cleaning up {:?}: {}", file_path, err);
+                }
+            }
+        }
+    }
+
+    // Uploads the archives as a single tar, next to an index object listing them.
+    // Archives removed in the meantime are left out.
+    pub async fn upload_bundle(&self, file_paths: &[PathBuf], route: &Route) {
+        let target = &route.target;
+        let mut members = vec![];
+        let mut corrupt = vec![];
+        for file_path in file_paths {
+            let member = match blocking(file_path, |path| bundle_member(&path)).await {
+                Ok(member) => member,
+                Err(err) => {
+                    warn!("leaving {:?} out of its bundle: {}", file_path, err);
+                    continue;
+                }
+            };
+            if let Some(reason) = self.corruption(file_path).await {
+                if !self.integrity.upload_corrupt {
+                    let key = object_key(route, &member.name, member.rotated_at);
+                    let (size, checksum) = (member.size, &member.sha256);
+                    self.quarantine(file_path, &target.bucket, &key, size, checksum, &reason)
+                        .await;
+                    continue;
+                }
+                warn!("{:?} is corrupt, bundling it anyway: {}", file_path, reason);
+                corrupt.push((file_path.to_str().unwrap_or_default(), reason));
+            }
+            members.push((file_path.clone(), member));
+        }
+        let first = match members.first() {
+            Some((_, member)) => member.clone(),
+            None => return,
+        };
+
+        let name = format!(
+            "{}.bundle-{}.tar",
+            Path::new(&first.name)
+                .file_stem()
+                .and_then(OsStr::to_str)
+                .unwrap_or_default(),
+            members.len()
+        );
+        let key = object_key(route, &name, first.rotated_at);
+        let key = key.as_str();
+        let paths: Vec<&str> = members
+            .iter()
+            .map(|(file_path, _)| file_path.to_str().unwrap_or_default())
+            .collect();
+
+        if self.dry_run {
+            let size = members.iter().map(|(_, member)| member.size).sum();
+            for endpoint in self.group.pending(None) {
+                dry_run::report(&DryRunEvent::Bundle {
+                    members: paths.clone(),
+                    endpoint: self.endpoint_name(endpoint),
+                    bucket: endpoint.bucket(&target.bucket),
+                    key,
+                    size,
+                });
+                if self.group.mode() == GroupMode::Failover {
+                    break;
+                }
+            }
+            return;
+        }
+
+        let tar_path = env::temp_dir().join(format!("logga-{}-{}", process::id(), name));
+        let bundled = members.clone();
+        let written = blocking(&tar_path, move |tar_path| {
+            bundle::write_tar(&tar_path, &bundled)?;
+            let size = fs::metadata(&tar_path)?.len();
+            Ok((size, history::sha256_file(&tar_path)?))
+        })
+        .await;
+        let (size, checksum) = match written {
+            Ok(written) => written,
+            Err(err) => {
+                error!("Problem bundling {:?}: {}", paths, err);
+                let _ = fs::remove_file(&tar_path);
+                return;
+            }
+        };
+        let index = BundleIndex {
+            host: hostname(),
+            members: members.iter().map(|(_, member)| member.clone()).collect(),
+        };
+        let age = (OffsetDateTime::now_utc() - first.rotated_at)
+            .try_into()
+            .unwrap_or_default();
+
+        self.update(|history| {
+            for (path, (_, member)) in paths.iter().zip(&members) {
+                let name = Some(member.name.as_str());
+                history.start_attempt(path, &target.bucket, key, name, member.size, &member.sha256);
+            }
+            for (path, reason) in &corrupt {
+                history.mark_corrupt(path, reason.clone());
+            }
+        });
+
+        let object = Object {
+            bucket: &target.bucket,
+            key,
+            metadata: HashMap::from([
+                (String::from("host"), hostname()),
+                (String::from("sha256"), checksum),
+                (String::from(bundle::BUNDLE_METADATA), String::from("tar")),
+                (String::from("members"), members.len().to_string()),
+            ]),
+            storage_class: target.storage_class(size, age),
+            corrupt: !corrupt.is_empty(),
+            index: serde_json::to_vec_pretty(&index).ok(),
+        };
+        let outcome = self.send(&paths, &tar_path, &object).await;
+        if let Err(err) = fs::remove_file(&tar_path) {
+            warn!("Problem removing {:?}: {}", tar_path, err);
+        }
+
+        match outcome {
+            Outcome::Failed => {}
+            Outcome::Satisfied => info!(
+                "bundled {} archives into {}, the remaining endpoints catch up later",
+                members.len(),
+                key
+            ),
+            Outcome::Complete => {
+                info!("bundled {} archives into {}", members.len(), key);
+                for (file_path, _) in &members {
+                    if let Err(err) = after_upload(file_path, &route.after_upload) {
+                        error!("Problem cleaning up {:?}: {}", file_path, err);
+                    }
+                }
+            }
+        }
+    }
+
+    // Uploads body_path to the endpoints still missing one of the archives at paths,
+    // recording the outcome for each of them.
+    async fn send(&self, paths: &[&str], body_path: &Path, object: &Object<'_>) -> Outcome {
+        // Endpoints which confirmed this content earlier are skipped, a retry only
+        // catches up the ones lagging behind
+        let pending: Vec<&Endpoint> = {
+            let history = self.history.lock().unwrap();
+            let pending: Vec<Vec<&Endpoint>> = paths
+                .iter()
+                .map(|path| self.group.pending(history.get(path)))
+                .collect();
+            self.group
+                .endpoints()
+                .iter()
+                .filter(|endpoint| {
+                    pending
+                        .iter()
+                        .any(|p| p.iter().any(|e| e.name == endpoint.name))
+                })
+                .collect()
+        };
+
+        let mut errors = vec![];
+        let mut outcomes = vec![];
+        for endpoint in pending {
+            info!("uploading {} to {}", object.key, endpoint.name);
+            let outcome = self.put(endpoint, body_path, object).await;
+            if let Err(reason) = &outcome {
+                error!(
+                    "Problem uploading {} to {}: {}",
+                    object.key, endpoint.name, reason
+                );
+                errors.push(match self.endpoint_name(endpoint) {
+                    Some(name) => format!("{}: {}", name, reason),
+                    None => reason.clone(),
+                });
+            }
+            let confirmed = outcome.is_ok();
+            outcomes.push((&endpoint.name, outcome));
+            if confirmed && self.group.mode() == GroupMode::Failover {
+                break;
+            }
+        }
+
+        // A single write for the archives and endpoints of the upload
+        self.record(|history| {
+            let mut outcome = Outcome::Complete;
+            for path in paths {
+                for (endpoint, result) in &outcomes {
+                    history.endpoint_finished(path, endpoint, result.clone());
+                }
+                let (satisfied, complete) = history.get(path).map_or((false, false), |record| {
+                    (self.group.satisfied(record), self.group.complete(record))
+                });
+                if satisfied {
+                    history.complete(path);
+                    if !complete {
+                        outcome = outcome.min(Outcome::Satisfied);
+                    }
+                } else {
+                    history.fail(path, errors.join("; "));
+                    outcome = Outcome::Failed;
+                }
+            }
+            outcome
+        })
+        .await
+    }
+
+    // The reason a zip failed its integrity check, None for intact archives and
+    // anything that isn't a zip.
+    async fn corruption(&self, file_path: &Path) -> Option<String> {
+        if !self.integrity.verify_zips || !integrity::is_zip(file_path) {
+            return None;
+        }
+        match blocking(file_path, |path| integrity::verify_zip(&path)).await {
+            Ok(Verdict::Intact) => None,
+            Ok(Verdict::Corrupt(reason)) => Some(reason),
+            Ok(Verdict::Unverifiable(reason)) => {
+                warn!("can't verify {:?}: {}", file_path, reason);
+                None
+            }
+            // Reading it for the upload reports the same problem
+            Err(err) => {
+                warn!("Problem verifying {:?}: {}", file_path, err);
+                None
+            }
+        }
+    }
+
+    // Moves a corrupt archive into quarantine instead of uploading it, recording why.
+    async fn quarantine(
+        &self,
+        file_path: &Path,
+        bucket: &str,
+        key: &str,
+        size: u64,
+        checksum: &str,
+        reason: &str,
+    ) {
+        let path_str = file_path.to_str().unwrap_or_default();
+        if self.dry_run {
+            dry_run::report(&DryRunEvent::Quarantine {
+                path: path_str,
+                reason,
+            });
+            return;
+        }
+
+        self.update(|history| history.start_attempt(path_str, bucket, key, None, size, checksum));
+        let quarantined = match integrity::quarantine(file_path) {
+            Ok(destination) => {
+                warn!(
+                    "{:?} is corrupt, moved to {:?}: {}",
+                    file_path, destination, reason
+                );
+                let reason = format!("corrupt, moved to {}: {}", destination.display(), reason);
+                self.record(|history| {
+                    history.quarantine(path_str, reason);
+                    quarantined(history)
+                })
+                .await
+            }
+            Err(err) => {
+                error!("Problem quarantining {:?}: {}", file_path, err);
+                self.record(|history| {
+                    history.fail(path_str, format!("corrupt: {}", reason));
+                    quarantined(history)
+                })
+                .await
+            }
+        };
+        self.health.set_quarantined(quarantined);
+    }
+
+    async fn put(
+        &self,
+        endpoint: &Endpoint,
+        body_path: &Path,
+        object: &Object<'_>,
+    ) -> Result<(), String> {
+        let bucket = endpoint.bucket(object.bucket);
+        let body = ByteStream::from_path(body_path)
+            .await
+            .map_err(|err| err.to_string())?;
+        endpoint
+            .client
+            .put_object()
+            .bucket(bucket)
+            .key(object.key)
+            .set_metadata(Some(object.metadata.clone()))
+            .set_storage_class(object.storage_class.cloned())
+            .set_tagging(object.corrupt.then(|| String::from(CORRUPT_TAG)))
+            .body(body)
+            .send()
+            .await
+            .map_err(|err| DisplayErrorContext(err).to_string())?;
+
+        if let Some(index) = &object.index {
+            endpoint
+                .client
+                .put_object()
+                .bucket(bucket)
+                .key(format!("{}{}", object.key, bundle::INDEX_SUFFIX))
+                .content_type("application/json")
+                .body(ByteStream::from(index.clone()))
+                .send()
+                .await
+                .map_err(|err| DisplayErrorContext(err).to_string())?;
+        }
+        Ok(())
+    }
+
+    // False when the history holds a completed upload of the archive as it is now
+    // on disk, so rescans don't upload the same archive over and over.
+    pub fn needs_upload(&self, file_path: &Path) -> bool {
+        let metadata = match fs::metadata(file_path) {
+            Ok(metadata) => metadata,
+            Err(_) => return false,
+        };
+        let modified = metadata
+            .modified()
+            .map(OffsetDateTime::from)
+            .unwrap_or_else(|_| OffsetDateTime::now_utc());
+
+        let path_str = file_path.to_str().unwrap_or_default();
+        match self.history.lock().unwrap().get(path_str) {
+            Some(record) if record.status == UploadStatus::Completed => {
+                record.size != metadata.len()
+                    || record.updated_at < modified
+                    || !self.group.complete(record)
+            }
+            _ => true,
+        }
+    }
+
+    // Backed up archives still on disk whose upload some replicas missed.
+    pub fn lagging(&self) -> Vec<PathBuf> {
+        self.history
+            .lock()
+            .unwrap()
+            .records()
+            .filter(|record| {
+                record.status == UploadStatus::Completed && !self.group.complete(record)
+            })
+            .map(|record| PathBuf::from(&record.path))
+            .filter(|path| path.exists())
+            .collect()
+    }
+
+    pub fn catch_up_interval(&self) -> Option<Duration> {
+        self.group.catch_up_interval()
+    }
+
+    // Drops the history of an upload that can't finish anymore because its archive is gone.
+    // Records an archive added to a bundle which isn't uploaded yet.
+    pub async fn queue(&self, file_path: &Path, route: &Route, size: u64) {
+        if self.dry_run {
+            return;
+        }
+        let path_str = file_path.to_str().unwrap_or_default();
+        self.record(|history| history.queue(path_str, &route.target.bucket, size))
+            .await;
+    }
+
+    pub async fn forget_unfinished(&self, file_path: &Path) {
+        if self.dry_run {
+            return;
+        }
+        let path_str = file_path.to_str().unwrap_or_default();
+        self.record(|history| history.remove_unfinished(path_str))
+            .await;
+    }
+
+    // Records when the deletion of a removed archive is to be mirrored. False when
+    // there's nothing to mirror, the archive was never backed up.
+    pub async fn schedule_deletion(&self, file_path: &Path, due_at: OffsetDateTime) -> bool {
+        let path_str = file_path.to_str().unwrap_or_default();
+        let completed = self
+            .history
+            .lock()
+            .unwrap()
+            .get(path_str)
+            .is_some_and(|record| record.status == UploadStatus::Completed);
+        if completed && !self.dry_run {
+            self.record(|history| history.schedule_deletion(path_str, due_at))
+                .await;
+        }
+        completed
+    }
+
+    pub async fn cancel_deletion(&self, file_path: &Path) {
+        if self.dry_run {
+            return;
+        }
+        let path_str = file_path.to_str().unwrap_or_default();
+        self.record(|history| history.cancel_deletion(path_str))
+            .await;
+    }
+
+    // Deletions scheduled before the helper stopped.
+    pub fn pending_deletions(&self) -> Vec<(PathBuf, OffsetDateTime)> {
+        self.history
+            .lock()
+            .unwrap()
+            .pending_deletions()
+            .into_iter()
+            .map(|(path, due_at)| (PathBuf::from(path), due_at))
+            .collect()
+    }
+
+    // Deletes or tags the object previously uploaded from file_path, unless the
+    // archive reappeared locally. Every attempt ends up in the audit log.
+    pub async fn mirror_deletion(&self, file_path: &Path, mode: DeletionMode, audit: &AuditLog) {
+        let path_str = file_path.to_str().unwrap_or_default();
+        let record = match self.history.lock().unwrap().get(path_str) {
+            Some(record) => record.clone(),
+            None => return,
+        };
+        let endpoints = self.group.holders(&record);
+
+        let skipped = if file_path.exists() {
+            Some(String::from("the archive reappeared"))
+        } else if record.status != UploadStatus::Completed {
+            Some(format!("its upload is {}", record.status))
+        } else if let Some(copy) = self.local_copy(&record) {
+            // Renamed within the watched directories, the data is still there
+            Some(format!("the archive lives on as {}", copy.display()))
+        } else if record.member.is_some() {
+            Some(String::from("its bundle holds other archives"))
+        } else if endpoints.is_empty() {
+            Some(String::from("no endpoint holds the archive"))
+        } else {
+            None
+        };
+        if let Some(reason) = skipped {
+            info!("keeping the backup of {:?}, {}", file_path, reason);
+            self.cancel_deletion(file_path).await;
+            if self.dry_run {
+                return;
+            }
+            let entry = AuditEntry {
+                time: OffsetDateTime::now_utc(),
+                mode,
+                path: path_str,
+                endpoint: None,
+                bucket: &record.bucket,
+                key: &record.key,
+                skipped: Some(reason),
+                error: None,
+            };
+            if let Err(err) = audit.append(&entry) {
+                error!("writing deletion audit log: {}", err);
+            }
+            return;
+        }
+        let key = &record.key;
+
+        let mut mirrored = true;
+        for endpoint in endpoints {
+            let bucket = endpoint.bucket(&record.bucket);
+            if self.dry_run {
+                dry_run::report(&DryRunEvent::MirrorDeletion {
+                    path: path_str,
+                    bucket,
+                    key,
+                    mode,
+                });
+                continue;
+            }
+
+            let outcome = match mode {
+                DeletionMode::Delete => endpoint
+                    .client
+                    .delete_object()
+                    .bucket(bucket)
+                    .key(key)
+                    .send()
+                    .await
+                    .map(|_| ())
+                    .map_err(|err| DisplayErrorContext(err).to_string()),
+                DeletionMode::Tag => tag_deleted(&endpoint.client, bucket, key).await,
+            };
+
+            let entry = AuditEntry {
+                time: OffsetDateTime::now_utc(),
+                mode,
+                path: path_str,
+                endpoint: self.endpoint_name(endpoint),
+                bucket,
+                key,
+                skipped: None,
+                error: outcome.as_ref().err().cloned(),
+            };
+            if let Err(err) = audit.append(&entry) {
+                error!("writing deletion audit log: {}", err);
+            }
+
+            match outcome {
+                Ok(()) => info!("mirrored deletion of {:?} to {}/{}", file_path, bucket, key),
+                Err(err) => {
+                    error!("Problem mirroring deletion of {:?}: {}", file_path, err);
+                    mirrored = false;
+                }
+            }
+        }
+
+        if mirrored && !self.dry_run {
+            self.record(|history| history.mark_deleted(path_str)).await;
+        }
+    }
+
+    // Another archive on disk with the record's content.
+    fn local_copy(&self, record: &UploadRecord) -> Option<PathBuf> {
+        self.history
+            .lock()
+            .unwrap()
+            .records()
+            .filter(|other| other.path != record.path && other.checksum == record.checksum)
+            .map(|other| PathBuf::from(&other.path))
+            .find(|path| path.exists())
+    }
+
+    fn endpoint_name<'a>(&self, endpoint: &'a Endpoint) -> Option<&'a str> {
+        self.group.is_group().then_some(endpoint.name.as_str())
+    }
+
+    // Changes the history in memory only, the next record writes it out.
+    fn update<T>(&self, f: impl FnOnce(&mut History) -> T) -> T {
+        f(&mut self.history.lock().unwrap())
+    }
+
+    // Changes the history and writes it out on the blocking pool if anything
+    // changed, the lock is only held to serialize it. A broken history must never block backups,
+    // failures are only logged.
+    async fn record<T>(&self, f: impl FnOnce(&mut History) -> T) -> T {
+        let (value, snapshot) = {
+            let mut history = self.history.lock().unwrap();
+            let value = f(&mut history);
+            (value, history.is_changed().then(|| history.snapshot()))
+        };
+        let snapshot = match snapshot {
+            Some(Ok(snapshot)) => snapshot,
+            Some(Err(err)) => {
+                warn!("{}", err);
+                return value;
+            }
+            None => return value,
+        };
+
+        // Writes run one at a time, a snapshot a later one overtook is skipped
+        let mut written = self.written.lock().await;
+        if *written >= snapshot.number() {
+            return value;
+        }
+        let number = snapshot.number();
+        match task::spawn_blocking(move || snapshot.write()).await {
+            Ok(Ok(())) => *written = number,
+            Ok(Err(err)) => warn!("{}", err),
+            Err(err) => warn!("writing upload history: {}", err),
+        }
+        value
+    }
+}
+
+// Adds the logga-deleted tag, keeping the object's other tags.
+async fn tag_deleted(client: &Client, bucket: &str, key: &str) -> Result<(), String> {
+    let mut tags = client
+        .get_object_tagging()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|err| DisplayErrorContext(err).to_string())?
+        .tag_set;
+    tags.retain(|tag| tag.key() != DELETED_TAG);
+    let deleted_at = OffsetDateTime::now_utc()
+        .format(&Rfc3339)
+        .unwrap_or_default();
+    tags.push(
+        Tag::builder()
+            .key(DELETED_TAG)
+            .value(deleted_at)
+            .build()
+            .map_err(|err| err.to_string())?,
+    );
+
+    let tagging = Tagging::builder()
+        .set_tag_set(Some(tags))
+        .build()
+        .map_err(|err| err.to_string())?;
+    client
+        .put_object_tagging()
+        .bucket(bucket)
+        .key(key)
+        .tagging(tagging)
+        .send()
+        .await
+        .map(|_| ())
+        .map_err(|err| DisplayErrorContext(err).to_string())
+}
+
+// What send uploads for one archive or a bundle of them.
+struct Object<'a> {
+    // The route's, endpoints may replace it
+    bucket: &'a str,
+    key: &'a str,
+    metadata: HashMap<String, String>,
+    storage_class: Option<&'a StorageClass>,
+    // Failed the integrity check, tagged corrupt=true
+    corrupt: bool,
+    // Bundle index, uploaded next to the object
+    index: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Outcome {
+    Failed,
+    // Enough endpoints confirmed, others still lag behind
+    Satisfied,
+    // Every endpoint which should hold the archives does
+    Complete,
+}
+
+fn object_key(route: &Route, file_name: &str, rotated_at: OffsetDateTime) -> String {
+    let key = route.target.key_template.render(&TemplateContext {
+        host: &hostname(),
+        filename: file_name,
+        time: rotated_at,
+    });
+    format!("{}{}", route.target.prefix, key)
+}
+
+fn quarantined(history: &History) -> usize {
+    history
+        .records()
+        .filter(|record| record.status == UploadStatus::Quarantined)
+        .count()
+}
+
+// Hashing and verifying read whole archives, they run on the blocking pool
+// rather than stalling the other uploads.
+async fn blocking<T, F>(path: &Path, read: F) -> io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(PathBuf) -> io::Result<T> + Send + 'static,
+{
+    let path = path.to_path_buf();
+    task::spawn_blocking(move || read(path))
+        .await
+        .unwrap_or_else(|err| Err(io::Error::other(err)))
+}
+
+fn bundle_member(file_path: &Path) -> io::Result<Member> {
+    let metadata = fs::metadata(file_path)?;
+    Ok(Member {
+        name: file_path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .unwrap_or_default()
+            .to_string(),
+        size: metadata.len(),
+        sha256: history::sha256_file(file_path)?,
+        rotated_at: metadata
+            .modified()
+            .map(OffsetDateTime::from)
+            .unwrap_or_else(|_| OffsetDateTime::now_utc()),
+    })
+}
+
+// Deletes or moves away a backed up archive, as the watch asks for.
+fn after_upload(file_path: &Path, options: &AfterUpload) -> io::Result<()> {
+    if let Some(dir) = &options.move_to {
+        let destination = Path::new(dir).join(file_path.file_name().unwrap_or_default());
+        // Renames fail across filesystems, copy those
+        if fs::rename(file_path, &destination).is_err() {
+            fs::copy(file_path, &destination)?;
+            fs::remove_file(file_path)?;
+        }
+        info!("moved {:?} to {:?}", file_path, destination);
+    } else if options.delete {
+        fs::remove_file(file_path)?;
+        info!("deleted {:?}", file_path);
+    }
+    Ok(())
+}
+
+// User-defined object metadata, sent as x-amz-meta-* headers.
+fn object_metadata(file_path: &Path, checksum: &str) -> HashMap<String, String> {
+    HashMap::from([
+        (String::from("host"), hostname()),
+        (String::from("sha256"), checksum.to_string()),
+        (
+            String::from("source-path"),
+            file_path.to_string_lossy().to_string(),
+        ),
+    ])
+}
+
+pub fn hostname() -> String {
+    whoami::fallible::hostname().unwrap_or_else(|_| whoami::devicename())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{after_upload, Uploader};
+    use crate::configuration::{
+        AfterUpload, Configuration, DeletionMode, GroupMode, Integrity, S3,
+    };
+    use crate::health::Health;
+    use crate::testing::{s3_client, stand_in};
+    use crate::uploader::audit::AuditLog;
+    use crate::uploader::group::{Endpoint, EndpointGroup};
+    use crate::uploader::history::{History, UploadStatus};
+    use crate::uploader::route::Route;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn archive_moved_or_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let done = dir.path().join("done");
+        fs::create_dir(&done).unwrap();
+        let archive = dir.path().join("a.zip");
+
+        fs::write(&archive, "logga").unwrap();
+        after_upload(&archive, &AfterUpload::default()).unwrap();
+        assert!(archive.exists());
+
+        let move_to = AfterUpload {
+            delete: true,
+            move_to: Some(done.to_string_lossy().to_string()),
+        };
+        after_upload(&archive, &move_to).unwrap();
+        assert!(!archive.exists());
+        assert_eq!(fs::read_to_string(done.join("a.zip")).unwrap(), "logga");
+
+        fs::write(&archive, "logga").unwrap();
+        let delete = AfterUpload {
+            delete: true,
+            move_to: None,
+        };
+        after_upload(&archive, &delete).unwrap();
+        assert!(!archive.exists());
+    }
+
+    #[tokio::test]
+    async fn tags_deleted_archives_unless_renamed() {
+        let (url, requests) = stand_in(|_| {
+            let tags =
+                "<Tagging><TagSet><Tag><Key>team</Key><Value>web</Value></Tag></TagSet></Tagging>";
+            (200, tags.to_string())
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+
+        let mut history = History::open(dir.path().join("history.json")).unwrap();
+        for (name, checksum, member) in [
+            ("a.zip", "abc", None),
+            ("b.zip", "def", None),
+            ("c.zip", "def", None),
+            ("d.zip", "ghi", Some("d.zip")),
+        ] {
+            history.start_attempt(&path(name), "logs", name, member, 3, checksum);
+            history.complete(&path(name));
+        }
+        // b.zip was renamed to c.zip
+        fs::write(path("c.zip"), "zip").unwrap();
+        let uploader = Uploader::new(
+            EndpointGroup::single(s3_client(&url)),
+            history,
+            Integrity::default(),
+            Arc::new(Health::default()),
+            false,
+        );
+        let audit_path = dir.path().join("audit.log");
+        let audit = AuditLog::new(&audit_path);
+
+        for name in ["a.zip", "b.zip", "d.zip"] {
+            uploader
+                .mirror_deletion(dir.path().join(name).as_path(), DeletionMode::Tag, &audit)
+                .await;
+        }
+
+        let read = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(read.path, "/logs/a.zip?tagging");
+        let tagged = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        let body = String::from_utf8(tagged.body).unwrap();
+        assert!(body.contains("<Key>team</Key>"));
+        assert!(body.contains("<Key>logga-deleted</Key>"));
+        assert!(requests.try_recv().is_err());
+
+        let history = uploader.history.lock().unwrap();
+        assert_eq!(
+            history.get(&path("a.zip")).unwrap().status,
+            UploadStatus::Deleted
+        );
+        assert_eq!(
+            history.get(&path("b.zip")).unwrap().status,
+            UploadStatus::Completed
+        );
+        let audit = fs::read_to_string(&audit_path).unwrap();
+        let skipped: Vec<&str> = audit
+            .lines()
+            .filter_map(|line| line.split(r#""skipped":""#).nth(1))
+            .collect();
+        assert_eq!(audit.lines().count(), 3);
+        assert!(skipped[0].starts_with("the archive lives on as"));
+        assert!(skipped[1].starts_with("its bundle holds other archives"));
+    }
+
+    #[tokio::test]
+    async fn mirrors_deletion_of_records_from_before_the_group() {
+        let (url, requests) = stand_in(|_| (204, String::new()));
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("a.zip");
+        let path = archive.to_str().unwrap();
+
+        // Uploaded without a group, to the s3 section's endpoint
+        let mut history = History::open(dir.path().join("history.json")).unwrap();
+        history.start_attempt(path, "logs", "a.zip", None, 3, "abc");
+        history.endpoint_finished(path, "s3", Ok(()));
+        history.complete(path);
+
+        let endpoints = ["primary", "backup"]
+            .into_iter()
+            .map(|name| Endpoint {
+                name: name.to_string(),
+                client: s3_client(&url),
+                bucket: None,
+            })
+            .collect();
+        let group = EndpointGroup::new(endpoints, GroupMode::Replicate, None, 0);
+        let uploader = Uploader::new(
+            group,
+            history,
+            Integrity::default(),
+            Arc::new(Health::default()),
+            false,
+        );
+        let audit_path = dir.path().join("audit.log");
+        uploader
+            .mirror_deletion(&archive, DeletionMode::Delete, &AuditLog::new(&audit_path))
+            .await;
+
+        assert_eq!(
+            requests.recv_timeout(Duration::from_secs(5)).unwrap().path,
+            "/logs/a.zip?x-id=DeleteObject"
+        );
+        assert!(requests.try_recv().is_err());
+        let audit = fs::read_to_string(&audit_path).unwrap();
+        assert_eq!(audit.lines().count(), 1);
+        assert!(audit.contains(r#""endpoint":"primary""#));
+        let history = uploader.history.lock().unwrap();
+        assert_eq!(history.get(path).unwrap().status, UploadStatus::Deleted);
+    }
+
+    #[tokio::test]
+    async fn bundle_upload_writes_the_history_once() {
+        // One each, a stand-in serves one connection at a time
+        let stand_ins = [
+            stand_in(|_| (200, String::new())),
+            stand_in(|_| (200, String::new())),
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("history.json");
+        let members: Vec<PathBuf> = ["a.zip", "b.zip"]
+            .into_iter()
+            .map(|name| dir.path().join(name))
+            .collect();
+        for member in &members {
+            fs::write(member, "logga").unwrap();
+        }
+
+        let endpoints = ["primary", "backup"]
+            .into_iter()
+            .zip(&stand_ins)
+            .map(|(name, (url, _))| Endpoint {
+                name: name.to_string(),
+                client: s3_client(url),
+                bucket: None,
+            })
+            .collect();
+        let uploader = Uploader::new(
+            EndpointGroup::new(endpoints, GroupMode::Replicate, None, 0),
+            History::open(&store).unwrap(),
+            Integrity {
+                verify_zips: false,
+                upload_corrupt: false,
+            },
+            Arc::new(Health::default()),
+            false,
+        );
+        let config = Configuration {
+            s3: S3 {
+                bucket: String::from("logs"),
+                key_template: String::from("{filename}"),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let watch = &config.watches(dir.path().to_str().unwrap())[0];
+        let route = Route::build(watch, &config.s3).unwrap();
+        uploader.upload_bundle(&members, &route).await;
+
+        // The tar and its index on both endpoints
+        for (_, requests) in &stand_ins {
+            assert_eq!(requests.try_iter().count(), 2);
+        }
+        let history = History::open(&store).unwrap();
+        for member in &members {
+            let record = history.get(member.to_str().unwrap()).unwrap();
+            assert_eq!(record.status, UploadStatus::Completed);
+            assert!(record.confirmed("primary") && record.confirmed("backup"));
+        }
+        // The upload's snapshot was the only one before this
+        let next = uploader.history.lock().unwrap().snapshot().unwrap();
+        assert_eq!(next.number(), 2);
+    }
+}
@@ -1,74 +1,673 @@
-use aws_sdk_s3::Client;
-use log::{error, info, warn};
-use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
-use std::ffi::OsStr;
-use std::fmt;
-use std::path::Path;
+use crate::configuration::{DeletionMode, MirrorDeletions, WatcherBackend, WatcherConfig};
+use crate::health::Health;
+use crate::uploader::audit::AuditLog;
+use crate::uploader::batch::{Batcher, Bundle};
+use crate::uploader::debounce::{Debouncer, FileEvent};
+use crate::uploader::route::{Route, Router};
+use crate::uploader::upload::Uploader;
+use log::{debug, info, warn};
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Config, Event, EventKind, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Interval;
 
-#[derive(Debug)]
-enum ExtensionKey {
-    Zip,
-}
+// Proves the event loop isn't stuck, see health::Health
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
 
-impl fmt::Display for ExtensionKey {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-pub async fn watch<P: AsRef<Path>>(
-    path: P,
-    client: &Client,
-    bucket: &String,
+// One watcher and one upload pipeline for all watches, the router picks each
+// archive's destination.
+pub async fn watch(
+    router: Router,
+    config: &WatcherConfig,
+    mirror: &MirrorDeletions,
+    uploader: Arc<Uploader>,
+    health: Arc<Health>,
 ) -> notify::Result<()> {
-    let (tx, rx) = std::sync::mpsc::channel();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let handler = move |res| {
+        let _ = tx.send(res);
+    };
+
+    let mut watcher: Box<dyn Watcher + Send> = match config.backend {
+        WatcherBackend::Native => Box::new(RecommendedWatcher::new(handler, Config::default())?),
+        WatcherBackend::Poll => Box::new(PollWatcher::new(
+            handler,
+            Config::default().with_poll_interval(Duration::from_millis(config.poll_interval_ms)),
+        )?),
+    };
+
+    let watch_dirs: Vec<PathBuf> = router.dirs().into_iter().map(Path::to_path_buf).collect();
+    for dir in &watch_dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut rescan_timer = match config.rescan_interval_seconds {
+        0 => None,
+        seconds => Some(tokio::time::interval(Duration::from_secs(seconds))),
+    };
+
+    // Retries replicas which missed uploads, see group::EndpointGroup
+    let mut catch_up_timer = uploader.catch_up_interval().map(tokio::time::interval);
+
+    let mut debouncer = Debouncer::new(Duration::from_millis(config.debounce_ms));
+    let mut batcher = Batcher::default();
+    let mut in_flight: HashMap<PathBuf, Upload> = HashMap::new();
+    let mut deletions = Deletions::new(mirror, &uploader);
+    if mirror.enabled {
+        deletions.resume();
+    }
+
+    // Archives which arrived while the helper was stopped, or whose upload or
+    // bundle it didn't finish
+    rescan(&router, &mut debouncer, &in_flight, &uploader);
 
-    let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
+    loop {
+        let deadline = debouncer.next_deadline();
+        let batch_deadline = batcher.next_deadline();
+        in_flight.retain(|_, upload| !upload.is_finished());
+        health.set_queued(debouncer.pending() + batcher.pending() + in_flight.len());
 
-    watcher.watch(path.as_ref(), RecursiveMode::NonRecursive)?;
+        tokio::select! {
+            res = rx.recv() => match res {
+                Some(Ok(event)) => {
+                    let renamed = renamed_within(&event, &watch_dirs);
+                    for file_event in classify(&event, &watch_dirs) {
+                        match &file_event {
+                            FileEvent::Removed(file_path) => {
+                                batcher.remove(file_path);
+                                cancel(file_path, &mut in_flight, &uploader).await;
+                                // Archives removed by the helper itself after their upload are kept
+                                let mirrored = router
+                                    .route(file_path)
+                                    .is_some_and(|route| !route.after_upload.removes_archive());
+                                if renamed.as_deref() == Some(file_path.as_path()) {
+                                    // Still there under its new name, inotify reported the old one gone first
+                                    deletions.cancel(file_path).await;
+                                } else if mirror.enabled && mirrored {
+                                    deletions.schedule(file_path).await;
+                                }
+                            }
+                            // The archive came back before the safety delay elapsed
+                            FileEvent::Changed(file_path) => deletions.cancel(file_path).await,
+                        }
+                        debouncer.push(&file_event, Instant::now());
+                    }
 
-    for res in rx {
-        match res {
-            Ok(event) => handle(&event, &client, &bucket).await,
-            Err(error) => log::error!("Error watching files: {error:?}"),
+                    // The kernel queue overflowed, events were dropped
+                    if event.need_rescan() {
+                        warn!("watcher asked for a rescan of {:?}", watch_dirs);
+                        rescan(&router, &mut debouncer, &in_flight, &uploader);
+                    }
+                }
+                Some(Err(error)) => {
+                    log::error!("Error watching files: {error:?}, rescanning {:?}", watch_dirs);
+                    rescan(&router, &mut debouncer, &in_flight, &uploader);
+                }
+                None => break,
+            },
+            _ = heartbeat.tick() => health.watcher_beat(),
+            _ = tick(&mut rescan_timer), if rescan_timer.is_some() => {
+                debug!("periodic rescan of {:?}", watch_dirs);
+                rescan(&router, &mut debouncer, &in_flight, &uploader);
+            }
+            _ = tick(&mut catch_up_timer), if catch_up_timer.is_some() => {
+                let now = Instant::now();
+                for file_path in uploader.lagging() {
+                    let uploading = in_flight
+                        .get(&file_path)
+                        .is_some_and(|upload| !upload.is_finished());
+                    if !uploading {
+                        debug!("catching up replicas of {:?}", file_path);
+                        debouncer.push(&FileEvent::Changed(file_path), now);
+                    }
+                }
+            }
+            _ = sleep_until(deadline), if deadline.is_some() => {
+                for file_path in debouncer.ready(Instant::now()) {
+                    let route = match router.route(&file_path) {
+                        Some(route) => route,
+                        None => continue,
+                    };
+                    if route.batch.is_some() {
+                        batch(file_path, route, &mut batcher, &uploader, &mut in_flight).await;
+                        continue;
+                    }
+                    let uploader = uploader.clone();
+                    let task_path = file_path.clone();
+                    let upload = tokio::spawn(async move { uploader.upload(&task_path, &route).await });
+                    if let Some(previous) = in_flight.insert(file_path, Upload::Archive(upload)) {
+                        // The archive was replaced while its previous version was uploading
+                        previous.abort();
+                    }
+                }
+            }
+            _ = sleep_until(batch_deadline), if batch_deadline.is_some() => {
+                for bundle in batcher.ready(Instant::now()) {
+                    upload_bundle(bundle, &uploader, &mut in_flight);
+                }
+            }
         }
     }
 
     Ok(())
 }
 
-async fn handle(evt: &Event, client: &Client, bucket: &String) {
-    if evt.kind.is_create() || evt.kind.is_remove() {
-        match evt.paths.first() {
-            Some(p) => {
-                let path_str = p.to_str().unwrap_or_default();
-                let file_path = Path::new(path_str);
-
-                if &file_path
-                    .extension()
-                    .and_then(OsStr::to_str)
-                    .unwrap_or_default()
-                    == &ExtensionKey::Zip.to_string().to_lowercase()
-                {
-                    info!("uploading file: {:?}", &file_path);
-                    let request = client
-                        .put_object()
-                        .bucket(bucket)
-                        .key(
-                            file_path
-                                .file_name()
-                                .and_then(OsStr::to_str)
-                                .unwrap_or_default(),
-                        )
-                        .send()
-                        .await;
-
-                    match request {
-                        Ok(_) => info!("{:?} backed up successfully", &file_path),
-                        Err(err) => error!("Problem uploading {:?}: {:?}", &file_path, err),
-                    }
+// An upload task. The members of a bundle share theirs, removing one of them
+// doesn't abort the others' upload.
+enum Upload {
+    Archive(JoinHandle<()>),
+    Bundle(Arc<JoinHandle<()>>),
+}
+
+impl Upload {
+    fn is_finished(&self) -> bool {
+        match self {
+            Upload::Archive(task) => task.is_finished(),
+            Upload::Bundle(task) => task.is_finished(),
+        }
+    }
+
+    fn abort(&self) {
+        if let Upload::Archive(task) = self {
+            task.abort();
+        }
+    }
+}
+
+// Adds an archive to its watch's bundle. It's recorded as pending meanwhile, a
+// restart before the bundle went out finds it in the startup rescan.
+async fn batch(
+    file_path: PathBuf,
+    route: Arc<Route>,
+    batcher: &mut Batcher,
+    uploader: &Arc<Uploader>,
+    in_flight: &mut HashMap<PathBuf, Upload>,
+) {
+    let size = match fs::metadata(&file_path) {
+        Ok(metadata) => metadata.len(),
+        Err(_) => return,
+    };
+    uploader.queue(&file_path, &route, size).await;
+    if let Some(bundle) = batcher.push(file_path, size, route, Instant::now()) {
+        upload_bundle(bundle, uploader, in_flight);
+    }
+}
+
+fn upload_bundle(
+    bundle: Bundle,
+    uploader: &Arc<Uploader>,
+    in_flight: &mut HashMap<PathBuf, Upload>,
+) {
+    let members = bundle.members.clone();
+    let uploader = uploader.clone();
+    let task = Arc::new(tokio::spawn(async move {
+        uploader.upload_bundle(&bundle.members, &bundle.route).await
+    }));
+    for member in members {
+        in_flight.insert(member, Upload::Bundle(task.clone()));
+    }
+}
+
+// Maps notify's platform specific events onto what happened to the archives.
+// A rename yields a removal of the old name and a change of the new one, so a
+// `foo.zip.tmp` renamed to `foo.zip` is uploaded once, under its final name.
+fn classify(evt: &Event, watch_dirs: &[PathBuf]) -> Vec<FileEvent> {
+    if evt.paths.is_empty() {
+        warn!("{:?} paths was empty", evt.kind);
+        return vec![];
+    }
+    debug!("{:?} {:?}", evt.kind, evt.paths);
+
+    let inside = |path: &PathBuf| inside(path, watch_dirs);
+    let changed = |path: &PathBuf| FileEvent::Changed(path.clone());
+    let removed = |path: &PathBuf| FileEvent::Removed(path.clone());
+
+    match evt.kind {
+        EventKind::Create(_) => evt.paths.iter().map(changed).collect(),
+        EventKind::Remove(_) => evt.paths.iter().map(removed).collect(),
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => match evt.paths.as_slice() {
+            [from, to] => {
+                let mut events = vec![removed(from)];
+                if inside(to) {
+                    events.push(changed(to));
                 }
+                events
+            }
+            _ => vec![],
+        },
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            evt.paths.iter().map(removed).collect()
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+            evt.paths.iter().map(changed).collect()
+        }
+        // kqueue and FSEvents can't tell the two sides of a rename apart
+        EventKind::Modify(ModifyKind::Name(_)) => evt
+            .paths
+            .iter()
+            .map(|path| {
+                if path.exists() {
+                    changed(path)
+                } else {
+                    removed(path)
+                }
+            })
+            .collect(),
+        EventKind::Modify(_) => evt.paths.iter().map(changed).collect(),
+        _ => vec![],
+    }
+}
+
+// The old name of an archive renamed within the watched directories.
+fn renamed_within(evt: &Event, watch_dirs: &[PathBuf]) -> Option<PathBuf> {
+    match (evt.kind, evt.paths.as_slice()) {
+        (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), [from, to])
+            if inside(to, watch_dirs) =>
+        {
+            Some(from.clone())
+        }
+        _ => None,
+    }
+}
+
+fn inside(path: &Path, watch_dirs: &[PathBuf]) -> bool {
+    path.parent()
+        .is_some_and(|parent| watch_dirs.iter().any(|dir| dir == parent))
+}
+
+// Queues the archives the watcher may have missed: anything in the directories
+// which isn't uploading right now and wasn't uploaded in its current state.
+fn rescan(
+    router: &Router,
+    debouncer: &mut Debouncer,
+    in_flight: &HashMap<PathBuf, Upload>,
+    uploader: &Uploader,
+) {
+    let mut archives = vec![];
+    for dir in router.dirs() {
+        match list_archives(dir, router) {
+            Ok(found) => archives.extend(found),
+            Err(err) => log::error!("Error rescanning {:?}: {}", dir, err),
+        }
+    }
+
+    let now = Instant::now();
+    for file_path in archives {
+        let uploading = in_flight
+            .get(&file_path)
+            .is_some_and(|upload| !upload.is_finished());
+        if !uploading && uploader.needs_upload(&file_path) {
+            info!("rescan found {:?}", file_path);
+            debouncer.push(&FileEvent::Changed(file_path), now);
+        }
+    }
+}
+
+fn list_archives(dir: &Path, router: &Router) -> io::Result<Vec<PathBuf>> {
+    let mut archives = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() && router.route(&entry.path()).is_some() {
+            archives.push(entry.path());
+        }
+    }
+    archives.sort();
+    Ok(archives)
+}
+
+async fn cancel(file_path: &Path, in_flight: &mut HashMap<PathBuf, Upload>, uploader: &Uploader) {
+    if let Some(upload) = in_flight.remove(file_path) {
+        if !upload.is_finished() {
+            upload.abort();
+            info!("{:?} was removed, upload cancelled", file_path);
+        }
+    }
+    uploader.forget_unfinished(file_path).await;
+}
+
+// Mirrors removals to the bucket once the safety delay elapsed, giving a
+// mistakenly deleted or temporarily moved archive the chance to come back.
+// The due times live in the upload history, a restart picks them up again.
+struct Deletions {
+    mode: DeletionMode,
+    delay: Duration,
+    uploader: Arc<Uploader>,
+    audit: Arc<AuditLog>,
+    tasks: HashMap<PathBuf, JoinHandle<()>>,
+}
+
+impl Deletions {
+    fn new(mirror: &MirrorDeletions, uploader: &Arc<Uploader>) -> Deletions {
+        Deletions {
+            mode: mirror.mode,
+            delay: Duration::from_secs(mirror.delay_seconds),
+            uploader: uploader.clone(),
+            audit: Arc::new(AuditLog::new(&mirror.audit_log_path)),
+            tasks: HashMap::new(),
+        }
+    }
+
+    fn resume(&mut self) {
+        for (file_path, due_at) in self.uploader.pending_deletions() {
+            info!("resuming mirrored deletion of {:?}", file_path);
+            self.spawn(file_path, due_at);
+        }
+    }
+
+    async fn schedule(&mut self, file_path: &Path) {
+        let due_at = OffsetDateTime::now_utc() + self.delay;
+        if self.uploader.schedule_deletion(file_path, due_at).await {
+            info!("{:?} was removed, mirroring in {:?}", file_path, self.delay);
+            self.spawn(file_path.to_path_buf(), due_at);
+        }
+    }
+
+    async fn cancel(&mut self, file_path: &Path) {
+        if let Some(task) = self.tasks.remove(file_path) {
+            task.abort();
+            self.uploader.cancel_deletion(file_path).await;
+        }
+    }
+
+    fn spawn(&mut self, file_path: PathBuf, due_at: OffsetDateTime) {
+        self.tasks.retain(|_, task| !task.is_finished());
+        // Overdue after a restart
+        let delay = (due_at - OffsetDateTime::now_utc())
+            .try_into()
+            .unwrap_or_default();
+        let uploader = self.uploader.clone();
+        let audit = self.audit.clone();
+        let mode = self.mode;
+        let task_path = file_path.clone();
+        let task = tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            uploader.mirror_deletion(&task_path, mode, &audit).await
+        });
+        if let Some(previous) = self.tasks.insert(file_path, task) {
+            previous.abort();
+        }
+    }
+}
+
+async fn tick(timer: &mut Option<Interval>) {
+    if let Some(timer) = timer {
+        timer.tick().await;
+    }
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    if let Some(deadline) = deadline {
+        tokio::time::sleep_until(deadline.into()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{batch, classify, list_archives, renamed_within, watch, Deletions};
+    use crate::configuration::{
+        Batch, Configuration, Integrity, MirrorDeletions, WatcherConfig, S3,
+    };
+    use crate::health::Health;
+    use crate::testing::{s3_client, stand_in};
+    use crate::uploader::batch::Batcher;
+    use crate::uploader::debounce::FileEvent;
+    use crate::uploader::group::EndpointGroup;
+    use crate::uploader::history::{History, UploadStatus};
+    use crate::uploader::route::{Route, Router};
+    use crate::uploader::upload::Uploader;
+    use notify::event::{AccessKind, CreateKind, ModifyKind, RemoveKind, RenameMode};
+    use notify::{Event, EventKind};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use time::OffsetDateTime;
+
+    fn event(kind: EventKind, paths: &[&str]) -> Event {
+        paths.iter().fold(Event::new(kind), |evt, path| {
+            evt.add_path(PathBuf::from(path))
+        })
+    }
+
+    fn logs() -> Vec<PathBuf> {
+        vec![PathBuf::from("/logs")]
+    }
+
+    fn changed(path: &str) -> FileEvent {
+        FileEvent::Changed(PathBuf::from(path))
+    }
+
+    fn removed(path: &str) -> FileEvent {
+        FileEvent::Removed(PathBuf::from(path))
+    }
+
+    #[test]
+    fn rename_into_final_name() {
+        let evt = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &["/logs/a.zip.tmp", "/logs/a.zip"],
+        );
+
+        assert_eq!(
+            classify(&evt, &logs()),
+            vec![removed("/logs/a.zip.tmp"), changed("/logs/a.zip")]
+        );
+        assert_eq!(
+            renamed_within(&evt, &logs()),
+            Some(PathBuf::from("/logs/a.zip.tmp"))
+        );
+    }
+
+    #[test]
+    fn rename_out_of_directory() {
+        let evt = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &["/logs/a.zip", "/archive/a.zip"],
+        );
+
+        assert_eq!(classify(&evt, &logs()), vec![removed("/logs/a.zip")]);
+        assert_eq!(renamed_within(&evt, &logs()), None);
+    }
+
+    #[test]
+    fn move_in_and_out() {
+        let moved_in = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+            &["/logs/a.zip"],
+        );
+        let moved_out = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+            &["/logs/a.zip"],
+        );
+
+        assert_eq!(classify(&moved_in, &logs()), vec![changed("/logs/a.zip")]);
+        assert_eq!(classify(&moved_out, &logs()), vec![removed("/logs/a.zip")]);
+    }
+
+    #[test]
+    fn create_and_remove() {
+        let created = event(EventKind::Create(CreateKind::File), &["/logs/a.zip"]);
+        let deleted = event(EventKind::Remove(RemoveKind::File), &["/logs/a.zip"]);
+
+        assert_eq!(classify(&created, &logs()), vec![changed("/logs/a.zip")]);
+        assert_eq!(classify(&deleted, &logs()), vec![removed("/logs/a.zip")]);
+    }
+
+    #[test]
+    fn access_is_ignored() {
+        let evt = event(EventKind::Access(AccessKind::Read), &["/logs/a.zip"]);
+        assert!(classify(&evt, &logs()).is_empty())
+    }
+
+    #[test]
+    fn rescan_lists_archives_only() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["b.zip", "a.zip", "notes.txt", "c.zip.tmp"] {
+            fs::write(dir.path().join(name), "logga").unwrap();
+        }
+        fs::create_dir(dir.path().join("old.zip")).unwrap();
+
+        let config = Configuration {
+            s3: S3 {
+                key_template: String::from("{filename}"),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let watches = config.watches(dir.path().to_str().unwrap());
+        let router = Router::new(vec![Route::build(&watches[0], &config.s3).unwrap()]);
+        let dir_path = dir.path().canonicalize().unwrap();
+
+        assert_eq!(
+            list_archives(&dir_path, &router).unwrap(),
+            vec![dir_path.join("a.zip"), dir_path.join("b.zip")]
+        )
+    }
+
+    #[tokio::test]
+    async fn deletions_resume_and_cancel() {
+        let (url, requests) = stand_in(|_| (204, String::new()));
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.zip");
+        let b = dir.path().join("b.zip");
+
+        let mut history = History::open(dir.path().join("history.json")).unwrap();
+        for (archive, key) in [(&a, "a.zip"), (&b, "b.zip")] {
+            let path = archive.to_str().unwrap();
+            history.start_attempt(path, "logs", key, None, 3, key);
+            history.complete(path);
+        }
+        // Due while the helper was stopped
+        let due_at = OffsetDateTime::now_utc() - time::Duration::minutes(1);
+        history.schedule_deletion(a.to_str().unwrap(), due_at);
+        let uploader = Arc::new(Uploader::new(
+            EndpointGroup::single(s3_client(&url)),
+            history,
+            Integrity::default(),
+            Arc::new(Health::default()),
+            false,
+        ));
+        let mirror = MirrorDeletions {
+            enabled: true,
+            audit_log_path: dir.path().join("audit.log").to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let mut deletions = Deletions::new(&mirror, &uploader);
+
+        deletions.resume();
+        deletions.tasks.remove(&a).unwrap().await.unwrap();
+        assert_eq!(
+            requests.try_recv().unwrap().path,
+            "/logs/a.zip?x-id=DeleteObject"
+        );
+        assert!(uploader.pending_deletions().is_empty());
+
+        deletions.schedule(&b).await;
+        assert_eq!(uploader.pending_deletions()[0].0, b);
+        // b.zip came back before the delay elapsed
+        deletions.cancel(&b).await;
+        assert!(uploader.pending_deletions().is_empty());
+        assert!(deletions.tasks.is_empty());
+        assert!(requests.try_recv().is_err());
+    }
+
+    fn uploader(url: &str, store: &Path) -> Arc<Uploader> {
+        Arc::new(Uploader::new(
+            EndpointGroup::single(s3_client(url)),
+            History::open(store).unwrap(),
+            Integrity {
+                verify_zips: false,
+                upload_corrupt: false,
+            },
+            Arc::new(Health::default()),
+            false,
+        ))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bundle_members_survive_a_restart() {
+        let (url, requests) = stand_in(|_| (200, String::new()));
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("history.json");
+        let logs = dir.path().join("logs");
+        fs::create_dir(&logs).unwrap();
+        let members = [logs.join("a.zip"), logs.join("b.zip")];
+        for member in &members {
+            fs::write(member, "logga").unwrap();
+        }
+
+        let config = Configuration {
+            s3: S3 {
+                bucket: String::from("logs"),
+                key_template: String::from("{filename}"),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut watch_config = config.watches(logs.to_str().unwrap()).remove(0);
+        watch_config.batch = Some(Batch {
+            max_bytes: 1024,
+            max_age_seconds: 1,
+        });
+        let route = Arc::new(Route::build(&watch_config, &config.s3).unwrap());
+
+        // Stopped while both waited in the bundle
+        {
+            let uploader = uploader(&url, &store);
+            let mut batcher = Batcher::default();
+            let mut in_flight = HashMap::new();
+            for member in &members {
+                batch(
+                    member.clone(),
+                    route.clone(),
+                    &mut batcher,
+                    &uploader,
+                    &mut in_flight,
+                )
+                .await;
+            }
+            assert_eq!(batcher.pending(), 2);
+        }
+        assert!(requests.try_recv().is_err());
+        let history = History::open(&store).unwrap();
+        for member in &members {
+            let record = history.get(member.to_str().unwrap()).unwrap();
+            assert_eq!(record.status, UploadStatus::Pending);
+        }
+
+        let uploader = uploader(&url, &store);
+        let router = Router::new(vec![Route::build(&watch_config, &config.s3).unwrap()]);
+        let watcher_config = WatcherConfig {
+            debounce_ms: 10,
+            ..Default::default()
+        };
+        let task_uploader = uploader.clone();
+        let watcher = tokio::spawn(async move {
+            let mirror = MirrorDeletions::default();
+            let health = Arc::new(Health::default());
+            watch(router, &watcher_config, &mirror, task_uploader, health).await
+        });
+        let uploaded = || members.iter().all(|member| !uploader.needs_upload(member));
+        for _ in 0..100 {
+            if uploaded() {
+                break;
             }
-            None => warn!("{:?} paths was empty", evt.kind),
+            tokio::time::sleep(Duration::from_millis(50)).await;
         }
+        watcher.abort();
+
+        assert!(uploaded());
+        // The tar and its index
+        assert_eq!(requests.try_iter().count(), 2);
     }
 }